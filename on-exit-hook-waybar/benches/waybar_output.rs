@@ -0,0 +1,83 @@
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
+use on_exit_hook_waybar::config::Config;
+use on_exit_hook_waybar::task::{generate_waybar_output, parse_due_date, sort_tasks, Task};
+use std::cmp::Ordering;
+use std::hint::black_box;
+
+/// The id, urgency and due date of each pending task.
+fn pending_rows(count: u32) -> Vec<(u32, f64, String)> {
+    (1..=count)
+        .map(|id| {
+            (
+                id,
+                f64::from(id % 10),
+                format!("202412{:02}T143002Z", id % 28 + 1),
+            )
+        })
+        .collect()
+}
+
+fn pending_tasks(count: u32) -> Vec<Task> {
+    let export: Vec<_> = pending_rows(count)
+        .into_iter()
+        .map(|(id, urgency, due)| {
+            serde_json::json!({
+                "id": id,
+                "description": format!("Pending task number {}", id),
+                "priority": (["H", "M", "L"][id as usize % 3]),
+                "due": due,
+                "urgency": urgency,
+            })
+        })
+        .collect();
+    serde_json::from_value(serde_json::Value::Array(export)).unwrap()
+}
+
+fn bench_generate_waybar_output(c: &mut Criterion) {
+    let tasks = pending_tasks(5000);
+    let config = Config::default();
+
+    c.bench_function("generate_waybar_output 5000 tasks", |b| {
+        b.iter(|| generate_waybar_output(black_box(&tasks), &config))
+    });
+}
+
+/// The sort against the comparator it replaced, which parsed both due dates on every
+/// comparison, on the same tasks.
+fn bench_sort_tasks(c: &mut Criterion) {
+    let tasks = pending_tasks(5000);
+    let rows = pending_rows(5000);
+    let mut group = c.benchmark_group("sort_tasks 5000 tasks");
+
+    group.bench_function("due dates parsed once", |b| {
+        b.iter_batched(
+            || tasks.clone(),
+            |mut tasks| {
+                sort_tasks(&mut tasks);
+                tasks
+            },
+            BatchSize::SmallInput,
+        )
+    });
+
+    group.bench_function("due dates parsed per comparison", |b| {
+        b.iter_batched(
+            || rows.clone(),
+            |mut rows| {
+                rows.sort_unstable_by(|a, b| {
+                    b.1.partial_cmp(&a.1)
+                        .unwrap_or(Ordering::Equal)
+                        .then_with(|| parse_due_date(&a.2).ok().cmp(&parse_due_date(&b.2).ok()))
+                        .then_with(|| a.0.cmp(&b.0))
+                });
+                rows
+            },
+            BatchSize::SmallInput,
+        )
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_generate_waybar_output, bench_sort_tasks);
+criterion_main!(benches);
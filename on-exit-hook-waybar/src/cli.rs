@@ -0,0 +1,204 @@
+use crate::errors::TaskHookWaybarError;
+use std::path::PathBuf;
+
+const DEFAULT_LOG_TAIL_LINES: usize = 20;
+
+#[derive(Debug, Default, PartialEq)]
+pub struct Args {
+    pub config_path: Option<PathBuf>,
+    pub profile: Option<String>,
+    pub all_profiles: bool,
+    pub check: bool,
+    /// Print the PID, name and command line of every matching waybar process and exit.
+    pub list_waybar: bool,
+    /// Check the config values and exit.
+    pub validate_config: bool,
+    pub check_stale: bool,
+    pub print_signal: bool,
+    /// Print the output to stdout instead of writing the file, without signalling waybar.
+    pub json: bool,
+    /// Like `json`, but also logs what would have been written and signalled.
+    pub dry_run: bool,
+    /// Write the output file and signal waybar as usual, and also print the output to stdout.
+    pub tee: bool,
+    /// The taskwarrior command that triggered the hook, from its `command:` argument.
+    pub task_command: Option<String>,
+    /// Print the last lines of the log file and exit.
+    pub log_tail: Option<usize>,
+    /// Do not print the success message (and the debug output) to stdout.
+    pub quiet: bool,
+    /// Print how long each step took to stderr.
+    pub timings: bool,
+    /// Remove the installed hook from `hooks_dir` (or the default hooks directory) and exit.
+    pub uninstall: bool,
+    pub hooks_dir: Option<PathBuf>,
+    /// Write a commented default config and exit.
+    pub export_config_template: Option<TemplateTarget>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum TemplateTarget {
+    Stdout,
+    File(PathBuf),
+}
+
+impl Args {
+    /// Parses the command line arguments (without the program name).
+    ///
+    /// Taskwarrior invokes hooks with `key:value` arguments such as `api:2` or
+    /// `command:add`; anything that is not a `--flag` is ignored.
+    pub fn parse<I>(args: I) -> Result<Self, TaskHookWaybarError>
+    where
+        I: IntoIterator<Item = String>,
+    {
+        let mut parsed = Args::default();
+        let mut args = args.into_iter().peekable();
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--config" => parsed.config_path = Some(expect_value(&arg, args.next())?.into()),
+                "--profile" => parsed.profile = Some(expect_value(&arg, args.next())?),
+                "--all-profiles" => parsed.all_profiles = true,
+                "--check" => parsed.check = true,
+                "--list-waybar" => parsed.list_waybar = true,
+                "--validate-config" => parsed.validate_config = true,
+                "--check-stale" => parsed.check_stale = true,
+                "--print-signal" => parsed.print_signal = true,
+                "--json" => parsed.json = true,
+                "--dry-run" => parsed.dry_run = true,
+                "--tee" => parsed.tee = true,
+                "--timings" => parsed.timings = true,
+                "--quiet" => parsed.quiet = true,
+                "--log-tail" => {
+                    let lines = args.next_if(|next| next.parse::<usize>().is_ok());
+                    parsed.log_tail = Some(
+                        lines
+                            .and_then(|lines| lines.parse().ok())
+                            .unwrap_or(DEFAULT_LOG_TAIL_LINES),
+                    );
+                }
+                "--uninstall" => {
+                    parsed.uninstall = true;
+                    parsed.hooks_dir = args
+                        .next_if(|next| !next.starts_with("--") && !next.contains(':'))
+                        .map(PathBuf::from);
+                }
+                "--export-config-template" => {
+                    let path = args.next_if(|next| !next.starts_with("--") && !next.contains(':'));
+                    parsed.export_config_template = Some(match path {
+                        Some(path) => TemplateTarget::File(path.into()),
+                        None => TemplateTarget::Stdout,
+                    });
+                }
+                flag if flag.starts_with("--") => {
+                    return Err(TaskHookWaybarError::InvalidArgument(format!(
+                        "Unknown flag {}",
+                        flag
+                    )))
+                }
+                hook_arg => {
+                    if let Some(command) = hook_arg.strip_prefix("command:") {
+                        parsed.task_command = Some(command.to_string());
+                    }
+                }
+            }
+        }
+
+        if parsed.profile.is_some() && parsed.all_profiles {
+            return Err(TaskHookWaybarError::InvalidArgument(
+                "--profile and --all-profiles are mutually exclusive".to_string(),
+            ));
+        }
+
+        Ok(parsed)
+    }
+}
+
+fn expect_value(flag: &str, value: Option<String>) -> Result<String, TaskHookWaybarError> {
+    value.ok_or_else(|| TaskHookWaybarError::InvalidArgument(format!("{} expects a value", flag)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Args, TaskHookWaybarError> {
+        Args::parse(args.iter().map(|s| s.to_string()))
+    }
+
+    #[test]
+    fn test_parse_ignores_taskwarrior_args() {
+        let args = parse(&["api:2", "command:add", "--profile", "work", "version:3.1.0"]).unwrap();
+        assert_eq!(
+            args,
+            Args {
+                profile: Some("work".to_string()),
+                task_command: Some("add".to_string()),
+                ..Args::default()
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_log_tail() {
+        assert_eq!(parse(&["--log-tail", "5"]).unwrap().log_tail, Some(5));
+        assert_eq!(
+            parse(&["--log-tail", "--json", "--timings"]).unwrap(),
+            Args {
+                log_tail: Some(DEFAULT_LOG_TAIL_LINES),
+                json: true,
+                timings: true,
+                ..Args::default()
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_export_config_template() {
+        assert_eq!(
+            parse(&["--export-config-template"])
+                .unwrap()
+                .export_config_template,
+            Some(TemplateTarget::Stdout)
+        );
+        assert_eq!(
+            parse(&["--export-config-template", "/tmp/config.toml", "--json"]).unwrap(),
+            Args {
+                export_config_template: Some(TemplateTarget::File("/tmp/config.toml".into())),
+                json: true,
+                ..Args::default()
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_uninstall() {
+        assert_eq!(
+            parse(&["--uninstall"]).unwrap(),
+            Args {
+                uninstall: true,
+                ..Args::default()
+            }
+        );
+        assert_eq!(
+            parse(&["--uninstall", "/tmp/hooks"]).unwrap().hooks_dir,
+            Some("/tmp/hooks".into())
+        );
+    }
+
+    #[test]
+    fn test_parse_invalid_args() {
+        assert!(matches!(
+            parse(&["--profile"]),
+            Err(TaskHookWaybarError::InvalidArgument(_))
+        ));
+        assert!(matches!(
+            parse(&["--profile", "work", "--all-profiles"]),
+            Err(TaskHookWaybarError::InvalidArgument(_))
+        ));
+        assert!(matches!(
+            parse(&["--unknown"]),
+            Err(TaskHookWaybarError::InvalidArgument(_))
+        ));
+    }
+}
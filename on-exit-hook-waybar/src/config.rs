@@ -0,0 +1,1485 @@
+use crate::errors::{ConfigError, TaskHookWaybarError};
+use crate::pango::{is_valid_hex_color, Palette};
+use crate::signals::{calculate_signal_number, ProcessMatcher};
+use crate::task_colors::TaskColors;
+use crate::template::Template;
+use crate::utils::expand_path;
+use chrono::format::{Item, StrftimeItems};
+use chrono::Locale;
+use log::warn;
+use serde::{Deserialize, Deserializer, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+const CONFIG_DIR_NAME: &str = "waybar-task-hook";
+const CONFIG_FILE_NAME: &str = "config.toml";
+pub const PALETTE_FILE_NAME: &str = "palette.toml";
+const DEFAULT_OUTPUT_FILE_NAME: &str = "waybar-tasks.json";
+const DEFAULT_LOG_FILE_NAME: &str = "waybar-task-hook.log";
+/// Holds `compact` or `full`, flipped by an external command like waybar's `on-click`.
+const MODE_FILE_NAME: &str = "waybar-task-mode";
+pub const DEFAULT_DUE_FORMAT: &str = "%a, %y-%m-%d %H:%M";
+
+/// Comment and, for options without a value by default, a commented-out example for every
+/// option in the template written by [`Config::template`].
+const TEMPLATE_DOCS: &[(&str, &str, Option<&str>)] = &[
+    ("filter", "Taskwarrior filter used for the export", None),
+    (
+        "extra_task_args",
+        "Passed to task after the filter, each entry as one argument (no shell involved).\n\
+         Anything here goes straight to taskwarrior, so only put trusted values here",
+        None,
+    ),
+    (
+        "use_context",
+        "Apply the active taskwarrior context (task context) on top of filter",
+        None,
+    ),
+    (
+        "output",
+        "Where the waybar json is written (default: ~/.cache/waybar-tasks.json)",
+        Some(r#""~/.cache/waybar-tasks.json""#),
+    ),
+    (
+        "log_file",
+        "Where the log is written (default: ~/.cache/waybar-task-hook.log)",
+        Some(r#""$XDG_CACHE_HOME/waybar-task-hook.log""#),
+    ),
+    (
+        "skip_unchanged",
+        "Neither rewrite the output file nor signal waybar when the output did not change",
+        None,
+    ),
+    (
+        "always_signal",
+        "Signal waybar even when skip_unchanged left the output file alone, to force a redraw",
+        None,
+    ),
+    (
+        "output_target",
+        "The bar reading output: \"waybar\", \"i3status-rust\" for a custom block with\n\
+         json = true (text, short_text and state, without tooltip), or \"plain\" for just the\n\
+         bar text without markup, e.g. for polybar, tmux or conky",
+        None,
+    ),
+    (
+        "pretty_output",
+        "Pretty-print the output file. Only for reading it yourself: waybar parses json line\n\
+         by line, so leave this off for the file your module reads",
+        None,
+    ),
+    (
+        "output_is_fifo",
+        "Set if output is a FIFO waybar reads from: it is then written without truncating,\n\
+         and skipped if nothing is reading",
+        None,
+    ),
+    (
+        "stale_after_minutes",
+        "--check-stale reports the output file as stale after this many minutes",
+        None,
+    ),
+    (
+        "signal_offset",
+        "The hook sends SIGRTMIN+signal_offset, must match waybar's \"signal\"",
+        None,
+    ),
+    (
+        "auto_signal",
+        "Take signal_offset from the \"signal\" of the waybar module whose exec mentions the\n\
+         output file, keeping signal_offset if there is none",
+        None,
+    ),
+    (
+        "waybar_config",
+        "The waybar config read for auto_signal (default: ~/.config/waybar/config.jsonc)",
+        Some(r#""~/.config/waybar/config.jsonc""#),
+    ),
+    (
+        "skip_if_no_waybar",
+        "Skip the export when no waybar process (see process_names) is running",
+        None,
+    ),
+    (
+        "strict_signal",
+        "Fail if no waybar process is found or /proc cannot be read; set to false to only\n\
+         log a warning",
+        None,
+    ),
+    (
+        "verify_before_signal",
+        "Read each waybar process again right before signalling it and skip it if it no\n\
+         longer matches, in case its PID was reused",
+        None,
+    ),
+    (
+        "process_names",
+        "The waybar processes to signal, a single name or a list",
+        None,
+    ),
+    (
+        "process_match",
+        "How to find waybar: \"comm\" (process name), \"cmdline\" (executable name in the\n\
+         command line) or \"regex\" (process_pattern anywhere in the command line)",
+        None,
+    ),
+    (
+        "process_pattern",
+        "The pattern for process_match = \"regex\"",
+        Some(r#""waybar-wrapper|/bin/waybar""#),
+    ),
+    (
+        "signal_backend",
+        "How to signal waybar: \"kill\" (the processes found above) or \"systemctl\"\n\
+         (the main process of systemd_unit, for waybar running as a systemd user service)",
+        None,
+    ),
+    ("systemd_unit", "The user unit for signal_backend = \"systemctl\"", None),
+    (
+        "only_on",
+        "Only regenerate after these taskwarrior commands, empty for all commands",
+        None,
+    ),
+    (
+        "sort",
+        "Task order: \"urgency\", \"newest\" or \"oldest\" (by creation date)",
+        None,
+    ),
+    (
+        "tooltip_sort",
+        "Re-sort only the tooltip by \"urgency\", \"due\" (soonest first) or \"priority\",\n\
+         the bar text keeps showing the first task of sort",
+        Some("\"due\""),
+    ),
+    (
+        "show_waiting_section",
+        "List waiting tasks in their own section at the end of the tooltip",
+        None,
+    ),
+    (
+        "max_tasks",
+        "Only render this many tasks after sorting, to stay fast with huge task lists",
+        Some("50"),
+    ),
+    (
+        "show_count",
+        "Append the number of matching tasks to the bar text (counting all of them, even\n\
+         beyond max_tasks)",
+        None,
+    ),
+    (
+        "newline_placeholder",
+        "Replaces line breaks in task descriptions",
+        None,
+    ),
+    (
+        "trim_descriptions",
+        "Remove whitespace around descriptions, and with collapse_whitespace also shrink\n\
+         every run of spaces inside them to one",
+        None,
+    ),
+    ("collapse_whitespace", "", None),
+    (
+        "escape_placeholders",
+        "Write % as %% and braces as {{ and }} in descriptions, so waybar's format does not\n\
+         take them for placeholders",
+        None,
+    ),
+    (
+        "missing_description_placeholder",
+        "Shown for tasks without a description, empty to disable",
+        None,
+    ),
+    (
+        "max_text_len",
+        "Truncate the description in the bar to this many characters",
+        Some("40"),
+    ),
+    (
+        "id_display",
+        "What goes in front of each task: its \"id\", \"uuid-short\" (the first 8 characters of\n\
+         its uuid, which unlike the id does not change) or \"none\"",
+        None,
+    ),
+    (
+        "field_order",
+        "Which parts of a task to show and in what order, out of \"description\", \"tags\",\n\
+         \"priority\", \"due\" and \"urgency\"",
+        None,
+    ),
+    (
+        "text_template",
+        "Build the bar text from a template instead of field_order. {id}, {description}, {tags},\n\
+         {priority}, {due} and {urgency} are replaced by the task's values, {#if overdue}...{/if}\n\
+         keeps its content only for overdue tasks, likewise for due, active and tagged, and\n\
+         {#if !overdue} for the opposite. Blocks can nest, {{ and }} are a literal { and }",
+        Some("\"{#if overdue}⚠ {/if}{description}{#if due} ({due}){/if}\""),
+    ),
+    (
+        "text_due_within_days",
+        "Show the most urgent task due within this many days (or overdue) in the bar, and the\n\
+         most urgent task overall if there is none. The tooltip still lists every task",
+        Some("3"),
+    ),
+    (
+        "rotate_top",
+        "Show the next of the first this many tasks in the bar each time the hook runs, like a\n\
+         ticker, instead of always the most urgent one",
+        Some("3"),
+    ),
+    (
+        "show_active_elapsed",
+        "Append how long the task in the bar has been started, like \"1 Write report ▶ 25m\"",
+        None,
+    ),
+    (
+        "show_next",
+        "Append the start of the next task's description to the bar text, like\n\
+         \"1 Buy milk → (next: Call bank)\"",
+        None,
+    ),
+    (
+        "text_mode",
+        "\"task\" shows the most urgent task in the bar, \"count\" only how many tasks there are",
+        None,
+    ),
+    (
+        "text_tie_mode",
+        "When several tasks share the top urgency, \"first\" shows the first of them and\n\
+         \"count\" shows \"3 tasks at urgency 12.0\" instead",
+        None,
+    ),
+    (
+        "compact_when_over",
+        "Drop the urgency, then the due date, then the priority from the bar text while it\n\
+         is longer than this many characters",
+        Some("40"),
+    ),
+    (
+        "max_tooltip_desc_len",
+        "Truncate descriptions in the tooltip to this many characters",
+        Some("60"),
+    ),
+    (
+        "min_urgency",
+        "Leave out tasks below this urgency, from the bar and the tooltip",
+        Some("2.0"),
+    ),
+    (
+        "all_filtered_text",
+        "Shown instead of \"No tasks.\" when there are tasks but min_urgency left out all of them",
+        None,
+    ),
+    (
+        "max_tooltip_bytes",
+        "Cut the tooltip off with … once it is this many bytes long, as very large tooltips can\n\
+         make GTK slow. With pango_markup it is cut after the last line that fits. Not applied to\n\
+         tooltip_format = \"json\"",
+        Some("8192"),
+    ),
+    (
+        "urgency_scale",
+        "Display urgency on a 0-10 scale where this raw urgency maps to 10",
+        Some("20.0"),
+    ),
+    (
+        "percentage_urgency",
+        "Report the top task's urgency as waybar's percentage, 100 at this raw urgency, on a\n\
+         \"linear\" or \"log\" scale (which spreads the low urgencies further apart)",
+        Some("20.0"),
+    ),
+    ("percentage_scale", "", None),
+    (
+        "urgency_trim_zeros",
+        "Show whole urgencies without decimals, like 42 instead of 42.00",
+        None,
+    ),
+    (
+        "clamp_negative_urgency",
+        "Display negative urgency as 0, sorting still uses the real value",
+        None,
+    ),
+    (
+        "tooltip_format",
+        "\"text\" for one line per task, or \"json\" for a json array of the tasks' fields\n\
+         (without the waiting section and tag summary), for scripts",
+        None,
+    ),
+    (
+        "tooltip_skip_top",
+        "Leave the task shown in the bar out of the tooltip",
+        None,
+    ),
+    (
+        "recurrence_marker",
+        "Marker appended to recurring tasks in the tooltip, empty to disable",
+        None,
+    ),
+    (
+        "recurrence_marker_in_text",
+        "Also show the recurrence marker in the bar text",
+        None,
+    ),
+    (
+        "class_hysteresis",
+        "Only change the urgency class once the urgency is this far past a threshold, so it\n\
+         does not flicker while hovering around it. 0 to disable",
+        None,
+    ),
+    (
+        "high_urgency_threshold",
+        "Urgency of the top task needed for the \"urgency-high\" class",
+        None,
+    ),
+    (
+        "medium_urgency_threshold",
+        "Urgency of the top task needed for the \"urgency-medium\" class",
+        None,
+    ),
+    (
+        "alt_source",
+        "What the \"alt\" field reports for waybar's format-icons: \"due\" (\"due-today\") or\n\
+         \"priority\" (\"high\", \"medium\", \"low\" or \"none\")",
+        None,
+    ),
+    (
+        "pango_markup",
+        "Color each task by its urgency class using pango markup and palette.toml",
+        None,
+    ),
+    (
+        "taskwarrior_colors",
+        "With pango_markup, color tasks by taskwarrior's color rules for the active state, tags\n\
+         and priorities (read once per run with task _show), and by urgency class otherwise",
+        None,
+    ),
+    (
+        "status_prefix",
+        "Mark tasks that are not pending in the tooltip: ⏸ waiting, ✓ completed, ✗ deleted,\n\
+         ↻ recurring, for filters that include them",
+        None,
+    ),
+    (
+        "annotation_count_marker",
+        "Append 📎 and the number of annotations to tasks that have any",
+        None,
+    ),
+    (
+        "due_gradient",
+        "With pango_markup, color tooltip lines from green to red as their due date nears\n\
+         (red once overdue) instead of by urgency",
+        None,
+    ),
+    (
+        "due_gradient_days",
+        "Tasks due this many days ahead or later are fully green",
+        None,
+    ),
+    (
+        "overdue_source",
+        "How overdue tasks are found, for due_gradient and text_due_within_days: \"computed\"\n\
+         (due before now) or \"tag\" (also every task with the +OVERDUE virtual tag, whatever\n\
+         its due date)",
+        None,
+    ),
+    ("show_tags", "Show the tags of each task as +tag", None),
+    (
+        "tag_colors",
+        "With pango_markup, color single tags",
+        Some(r##"{ "+urgent" = "#f38ba8" }"##),
+    ),
+    (
+        "tag_summary",
+        "Add a footer counting tasks per tag like \"+work:3 +home:2\" to the tooltip",
+        None,
+    ),
+    (
+        "tag_summary_include_virtual",
+        "Also count virtual tags like +PENDING in the footer",
+        None,
+    ),
+    (
+        "urgency_bar",
+        "Prefix each tooltip line with a bar showing its urgency relative to the most urgent task",
+        None,
+    ),
+    ("due_format", "strftime format of due dates", None),
+    (
+        "locale",
+        "Locale for weekday and month names in due_format (default English)",
+        Some(r#""de_DE""#),
+    ),
+    (
+        "priority_label",
+        "Labels in front of the fields, like \"Prio: H\". Leave one empty to show only the value",
+        None,
+    ),
+    ("due_label", "", None),
+    ("scheduled_label", "", None),
+    ("urgency_label", "", None),
+    (
+        "priority_style",
+        "\"label\" shows priorities as \"Prio: H\", \"bang\" as \"!!!\", \"!!\" and \"!\"",
+        None,
+    ),
+    (
+        "date_preference",
+        "Which date to show for tasks with both: \"due\", \"scheduled\" or \"earliest\" (the\n\
+         sooner of the two, labelled)",
+        None,
+    ),
+    (
+        "no_due_marker",
+        "Shown in the tooltip in place of the due date of tasks without one, like \"(no due)\"",
+        None,
+    ),
+    (
+        "smart_due",
+        "Show \"Due today 14:30\", \"Due tomorrow 14:30\" or the weekday for tasks due within a week",
+        None,
+    ),
+    (
+        "relative_due",
+        "Show how far away the due date is, like \"Due in 1h 59m\" or \"Due 3d ago\". With\n\
+         relative_granularity = \"coarse\" it is rounded to the largest unit, like \"Due in 2h\"",
+        None,
+    ),
+    ("relative_granularity", "", None),
+    (
+        "log_changes",
+        "Log which tasks were added, removed or reordered since the previous export",
+        None,
+    ),
+    (
+        "extra_outputs",
+        "Further files written from the same task export, each with its own text_mode,\n\
+         e.g. for a second waybar module showing only the count",
+        Some("[[extra_outputs]]\noutput = \"~/.cache/waybar-task-count.json\"\ntext_mode = \"count\""),
+    ),
+    (
+        "profiles",
+        "One profile per bar, selected with --profile or --all-profiles. Each can override\n\
+         filter, output and signal_offset",
+        Some("[[profiles]]\nname = \"work\"\nfilter = \"status:pending project:work\""),
+    ),
+];
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub filter: String,
+    /// Passed to `task` after the filter, each as one argument without going through a shell.
+    pub extra_task_args: Vec<String>,
+    /// Applies the active taskwarrior context on top of `filter`.
+    pub use_context: bool,
+    pub output: Option<PathBuf>,
+    pub log_file: Option<PathBuf>,
+    /// Skips writing and signalling when the output file already holds the same output.
+    pub skip_unchanged: bool,
+    /// Signals waybar even if `skip_unchanged` skipped the write.
+    pub always_signal: bool,
+    /// Which bar's format the output is written in.
+    pub output_target: OutputTarget,
+    /// Pretty-prints the output file for reading it by hand.
+    pub pretty_output: bool,
+    /// Writes FIFO-safe (no truncation, never blocking without a reader) if `output` is a FIFO.
+    pub output_is_fifo: bool,
+    /// `--check-stale` reports the output as stale when it is older than this.
+    pub stale_after_minutes: u64,
+    pub signal_offset: i32,
+    /// Reads `signal_offset` from the waybar module showing the output file.
+    pub auto_signal: bool,
+    /// The waybar config read for `auto_signal`, waybar's own default if unset.
+    pub waybar_config: Option<PathBuf>,
+    /// Skips the export entirely while no waybar process is running.
+    pub skip_if_no_waybar: bool,
+    /// Fail when no waybar process is found instead of only logging a warning.
+    pub strict_signal: bool,
+    /// Re-checks each process right before signalling it, against PID reuse.
+    pub verify_before_signal: bool,
+    /// The names of the waybar processes to signal, a single name is accepted as well.
+    #[serde(alias = "process_name", deserialize_with = "one_or_many")]
+    pub process_names: Vec<String>,
+    /// How the waybar process to signal is found.
+    pub process_match: ProcessMatch,
+    /// The pattern for `process_match = "regex"`, matched against the command line.
+    pub process_pattern: Option<String>,
+    /// How waybar is signalled.
+    pub signal_backend: SignalBackend,
+    /// The user unit signalled with `signal_backend = "systemctl"`.
+    pub systemd_unit: String,
+    /// Only regenerate for these taskwarrior commands, empty to run for all of them.
+    pub only_on: Vec<String>,
+    pub sort: SortKey,
+    /// Re-sorts the tooltip lines only, unset keeps the order of `sort`.
+    pub tooltip_sort: Option<TooltipSort>,
+    /// Lists the waiting tasks in their own tooltip section, from a second export.
+    pub show_waiting_section: bool,
+    /// Only renders this many tasks after sorting.
+    pub max_tasks: Option<usize>,
+    /// Appends the number of matching tasks to `text`, counting those left out by `max_tasks`.
+    pub show_count: bool,
+    /// Replaces line breaks in task descriptions.
+    pub newline_placeholder: String,
+    /// Trims whitespace around descriptions.
+    pub trim_descriptions: bool,
+    /// Trims descriptions and collapses whitespace inside them to single spaces.
+    pub collapse_whitespace: bool,
+    /// Doubles `%`, `{` and `}` in descriptions.
+    pub escape_placeholders: bool,
+    /// Shown in place of the description for tasks without one, empty to disable.
+    pub missing_description_placeholder: String,
+    /// Truncates the description shown in the bar to this many characters.
+    pub max_text_len: Option<usize>,
+    /// The identifier in front of each task.
+    pub id_display: IdDisplay,
+    /// Which parts of a task to show, in this order.
+    pub field_order: Vec<Field>,
+    /// Renders `text` from this template instead of `field_order`.
+    pub text_template: Option<Template>,
+    /// Only tasks due within this many days are shown in `text`, if there are any.
+    pub text_due_within_days: Option<f64>,
+    /// Rotates `text` through this many tasks from the top, one step per run.
+    pub rotate_top: Option<usize>,
+    /// Which of the `rotate_top` tasks this run shows, kept in a file between runs.
+    #[serde(skip)]
+    pub rotation_index: usize,
+    /// Appends how long the task in `text` has been started.
+    pub show_active_elapsed: bool,
+    /// Appends the start of the second task's description to `text`.
+    pub show_next: bool,
+    /// What `text` shows.
+    pub text_mode: TextMode,
+    /// Logs how the tasks changed since the previous run, kept in a file next to the output.
+    pub log_changes: bool,
+    /// Further files written from the same export, e.g. a count next to the task list.
+    pub extra_outputs: Vec<ExtraOutput>,
+    /// What `text` shows when several tasks share the top urgency.
+    pub text_tie_mode: TextTieMode,
+    /// Drops urgency, then due date, then priority from `text` while it is longer than this.
+    pub compact_when_over: Option<usize>,
+    /// Truncates each description in the tooltip to this many characters.
+    pub max_tooltip_desc_len: Option<usize>,
+    /// Leaves out tasks below this urgency.
+    pub min_urgency: Option<f64>,
+    /// Shown when `min_urgency` left out every task.
+    pub all_filtered_text: String,
+    /// Cuts the text tooltip down to this many bytes.
+    pub max_tooltip_bytes: Option<usize>,
+    /// Displays urgency on a 0-10 scale where this raw urgency maps to 10.
+    pub urgency_scale: Option<f64>,
+    /// Sets `percentage` from the top task's urgency, which is 100 at this raw urgency.
+    pub percentage_urgency: Option<f64>,
+    pub percentage_scale: PercentageScale,
+    /// Displays whole urgencies without the `.00`.
+    pub urgency_trim_zeros: bool,
+    /// Displays negative urgency as 0, without changing the sort order.
+    pub clamp_negative_urgency: bool,
+    pub tooltip_format: TooltipFormat,
+    /// Leaves the task already shown in `text` out of the tooltip.
+    pub tooltip_skip_top: bool,
+    /// Appended to the description of recurring tasks, empty to disable.
+    pub recurrence_marker: String,
+    pub recurrence_marker_in_text: bool,
+    /// How far past a threshold the urgency has to be to change the urgency class the
+    /// previous run wrote, 0 to disable.
+    pub class_hysteresis: f64,
+    /// The urgency class in the previous output, read at startup for `class_hysteresis`.
+    #[serde(skip)]
+    pub previous_urgency_class: Option<String>,
+    /// Minimum urgency of the top task for the `urgency-high` class.
+    pub high_urgency_threshold: f64,
+    /// Minimum urgency of the top task for the `urgency-medium` class.
+    pub medium_urgency_threshold: f64,
+    pub alt_source: AltSource,
+    /// Colors task lines by their urgency class using pango markup.
+    pub pango_markup: bool,
+    /// Prefixes tooltip lines of tasks that are not pending with a status symbol.
+    pub status_prefix: bool,
+    /// Appends `📎n` to tasks with n annotations.
+    pub annotation_count_marker: bool,
+    /// With `pango_markup`, colors tooltip lines from green to red as their due date nears.
+    pub due_gradient: bool,
+    /// Tasks due this many days ahead or later are fully green.
+    pub due_gradient_days: f64,
+    /// Whether to trust taskwarrior's `+OVERDUE` virtual tag.
+    pub overdue_source: OverdueSource,
+    /// Shows the task's tags as `+tag` tokens.
+    pub show_tags: bool,
+    /// Pango colors for individual tags, keyed with or without the leading `+`.
+    pub tag_colors: HashMap<String, String>,
+    /// strftime format of the due date.
+    pub due_format: String,
+    /// Locale for weekday and month names in `due_format`, like `de_DE`. English if unset.
+    pub locale: Option<String>,
+    /// Labels in front of the field values, none if empty.
+    pub priority_label: String,
+    pub due_label: String,
+    pub scheduled_label: String,
+    pub urgency_label: String,
+    /// How the priority is shown.
+    pub priority_style: PriorityStyle,
+    /// Which of the due and scheduled dates is shown.
+    pub date_preference: DatePreference,
+    /// Shows "today", "tomorrow" or the weekday instead of the date for tasks due within a week.
+    pub smart_due: bool,
+    /// Shows the time until (or since) the due date instead of the date, before `smart_due`.
+    pub relative_due: bool,
+    pub relative_granularity: RelativeGranularity,
+    /// Shown in tooltip lines in place of a missing due date, empty to disable.
+    pub no_due_marker: String,
+    /// Prefixes each tooltip line with a bar showing its urgency relative to the most urgent task.
+    pub urgency_bar: bool,
+    /// Adds a `+tag:count` footer to the tooltip.
+    pub tag_summary: bool,
+    /// Also counts virtual tags like `+PENDING` in the footer.
+    pub tag_summary_include_virtual: bool,
+    /// Loaded from `palette.toml` next to the config file.
+    #[serde(skip)]
+    pub palette: Palette,
+    /// Colors tasks like taskwarrior does with pango markup, before the palette.
+    pub taskwarrior_colors: bool,
+    /// Read from taskwarrior for `taskwarrior_colors`.
+    #[serde(skip)]
+    pub task_colors: TaskColors,
+    pub profiles: Vec<Profile>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            filter: "status:pending".to_string(),
+            extra_task_args: Vec::new(),
+            use_context: true,
+            output: None,
+            log_file: None,
+            skip_unchanged: false,
+            always_signal: false,
+            output_target: OutputTarget::Waybar,
+            pretty_output: false,
+            output_is_fifo: false,
+            stale_after_minutes: 24 * 60,
+            signal_offset: 8,
+            auto_signal: false,
+            waybar_config: None,
+            skip_if_no_waybar: false,
+            strict_signal: true,
+            verify_before_signal: false,
+            process_names: vec!["waybar".to_string()],
+            process_match: ProcessMatch::Comm,
+            process_pattern: None,
+            signal_backend: SignalBackend::Kill,
+            systemd_unit: "waybar.service".to_string(),
+            only_on: Vec::new(),
+            sort: SortKey::Urgency,
+            tooltip_sort: None,
+            show_waiting_section: false,
+            max_tasks: None,
+            show_count: false,
+            newline_placeholder: " ".to_string(),
+            trim_descriptions: false,
+            collapse_whitespace: false,
+            escape_placeholders: false,
+            missing_description_placeholder: "(no description)".to_string(),
+            max_text_len: None,
+            max_tooltip_desc_len: None,
+            min_urgency: None,
+            all_filtered_text: "No matching tasks.".to_string(),
+            max_tooltip_bytes: None,
+            compact_when_over: None,
+            id_display: IdDisplay::Id,
+            field_order: vec![
+                Field::Description,
+                Field::Tags,
+                Field::Priority,
+                Field::Due,
+                Field::Urgency,
+            ],
+            text_template: None,
+            text_due_within_days: None,
+            rotate_top: None,
+            rotation_index: 0,
+            show_active_elapsed: false,
+            show_next: false,
+            text_tie_mode: TextTieMode::First,
+            text_mode: TextMode::Task,
+            extra_outputs: Vec::new(),
+            log_changes: false,
+            urgency_scale: None,
+            percentage_urgency: None,
+            percentage_scale: PercentageScale::Linear,
+            urgency_trim_zeros: false,
+            clamp_negative_urgency: false,
+            tooltip_format: TooltipFormat::Text,
+            tooltip_skip_top: false,
+            recurrence_marker: "🔁".to_string(),
+            recurrence_marker_in_text: false,
+            class_hysteresis: 0.0,
+            previous_urgency_class: None,
+            high_urgency_threshold: 10.0,
+            medium_urgency_threshold: 5.0,
+            alt_source: AltSource::Due,
+            pango_markup: false,
+            status_prefix: false,
+            annotation_count_marker: false,
+            due_gradient: false,
+            due_gradient_days: 7.0,
+            overdue_source: OverdueSource::Computed,
+            show_tags: false,
+            tag_colors: HashMap::new(),
+            due_format: DEFAULT_DUE_FORMAT.to_string(),
+            locale: None,
+            priority_label: "Prio".to_string(),
+            due_label: "Due".to_string(),
+            scheduled_label: "Scheduled".to_string(),
+            urgency_label: "Urgency".to_string(),
+            priority_style: PriorityStyle::Label,
+            date_preference: DatePreference::Due,
+            smart_due: false,
+            relative_due: false,
+            relative_granularity: RelativeGranularity::Fine,
+            no_due_marker: String::new(),
+            urgency_bar: false,
+            tag_summary: false,
+            tag_summary_include_virtual: false,
+            palette: Palette::default(),
+            taskwarrior_colors: false,
+            task_colors: TaskColors::default(),
+            profiles: Vec::new(),
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum SortKey {
+    /// Most urgent first.
+    #[default]
+    Urgency,
+    /// Most recently created first, by `entry` or else `modified`.
+    Newest,
+    /// Least recently created first, by `entry` or else `modified`.
+    Oldest,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum TooltipSort {
+    /// Most urgent first.
+    Urgency,
+    /// Soonest due first, tasks without a due date last.
+    Due,
+    /// `H`, `M`, `L`, then tasks without a priority.
+    Priority,
+}
+
+/// Accepts `"name"` as well as `["name", ...]`.
+fn one_or_many<'de, D>(deserializer: D) -> Result<Vec<String>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One(String),
+        Many(Vec<String>),
+    }
+
+    Ok(match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::One(name) => vec![name],
+        OneOrMany::Many(names) => names,
+    })
+}
+
+/// How the priority field is shown.
+#[derive(Deserialize, Serialize, Debug, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum PriorityStyle {
+    /// `Prio: H`.
+    #[default]
+    Label,
+    /// `!!!` for H, `!!` for M and `!` for L.
+    Bang,
+}
+
+/// How exactly `relative_due` shows the time until the due date.
+#[derive(Deserialize, Serialize, Debug, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum RelativeGranularity {
+    /// The two largest units, like `1h 59m`.
+    #[default]
+    Fine,
+    /// Rounded to the largest unit, like `2h`.
+    Coarse,
+}
+
+/// How urgency maps to `percentage`.
+#[derive(Deserialize, Serialize, Debug, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum PercentageScale {
+    #[default]
+    Linear,
+    /// By `ln(1 + urgency)`.
+    Log,
+}
+
+/// Which bar reads the output.
+#[derive(Deserialize, Serialize, Debug, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum OutputTarget {
+    #[default]
+    Waybar,
+    /// A custom block of i3status-rust with `json = true`.
+    I3statusRust,
+    /// One line of plain text.
+    Plain,
+}
+
+/// How overdue tasks are recognized.
+#[derive(Deserialize, Serialize, Debug, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum OverdueSource {
+    /// Due before now.
+    #[default]
+    Computed,
+    /// Tagged `OVERDUE` by taskwarrior, or else due before now.
+    Tag,
+}
+
+/// Which date a task with both a due and a scheduled date shows.
+#[derive(Deserialize, Serialize, Debug, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum DatePreference {
+    /// Only the due date.
+    #[default]
+    Due,
+    /// The scheduled date, or the due date if there is none.
+    Scheduled,
+    /// Whichever comes first.
+    Earliest,
+}
+
+/// What `text` shows.
+#[derive(Deserialize, Serialize, Debug, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum TextMode {
+    /// The most urgent task.
+    #[default]
+    Task,
+    /// The number of tasks, like `3 tasks`.
+    Count,
+}
+
+/// How much the bar text shows, switched at runtime through the mode file.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DisplayMode {
+    /// Only the task.
+    Compact,
+    /// The task, the number of tasks and the start of the next one.
+    Full,
+}
+
+/// The identifier in front of each task.
+#[derive(Deserialize, Serialize, Debug, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum IdDisplay {
+    /// The working-set id, like `12`.
+    #[default]
+    Id,
+    /// The first 8 characters of the uuid, falling back to the id for tasks without one.
+    UuidShort,
+    None,
+}
+
+/// Another file written from the same export, with its own `text_mode`.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct ExtraOutput {
+    pub output: PathBuf,
+    #[serde(default)]
+    pub text_mode: TextMode,
+}
+
+/// What `text` shows when several tasks tie for the top urgency.
+#[derive(Deserialize, Serialize, Debug, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum TextTieMode {
+    /// The first of them, as if there was no tie.
+    #[default]
+    First,
+    /// How many tasks share the urgency, like `3 tasks at urgency 12.0`.
+    Count,
+}
+
+/// What the `alt` field tells waybar about the top task.
+#[derive(Deserialize, Serialize, Debug, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum AltSource {
+    /// `due-today` if it is due today.
+    #[default]
+    Due,
+    /// Its priority as `high`, `medium`, `low` or `none`.
+    Priority,
+}
+
+#[derive(Deserialize, Serialize, Debug, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum TooltipFormat {
+    /// One line per task.
+    #[default]
+    Text,
+    /// A json array with one object of rendered fields per task, for scripts.
+    Json,
+}
+
+/// The parts of a rendered task, after its id.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Field {
+    Description,
+    Tags,
+    Priority,
+    Due,
+    Urgency,
+}
+
+#[derive(Deserialize, Serialize, Debug, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ProcessMatch {
+    /// The process name as the kernel reports it.
+    #[default]
+    Comm,
+    /// The executable's file name in the command line.
+    Cmdline,
+    /// `process_pattern` anywhere in the command line.
+    Regex,
+}
+
+#[derive(Deserialize, Serialize, Debug, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum SignalBackend {
+    /// `kill(2)` for every process found with `process_match`.
+    #[default]
+    Kill,
+    /// `systemctl --user kill` for the main process of `systemd_unit`.
+    Systemctl,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Profile {
+    pub name: String,
+    pub filter: Option<String>,
+    pub output: Option<PathBuf>,
+    pub signal_offset: Option<i32>,
+}
+
+impl Config {
+    pub fn default_dir() -> Option<PathBuf> {
+        dirs::config_dir().map(|dir| dir.join(CONFIG_DIR_NAME))
+    }
+
+    pub fn default_path() -> Option<PathBuf> {
+        Self::default_dir().map(|dir| dir.join(CONFIG_FILE_NAME))
+    }
+
+    /// Loads the config at `path`, falling back to the defaults if the file does not exist.
+    pub fn load(path: &Path) -> Result<Self, TaskHookWaybarError> {
+        match fs::read_to_string(path) {
+            Ok(content) => Ok(Self::parse(&content)?),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(Config::default()),
+            Err(e) => Err(TaskHookWaybarError::with_path(path)(e)),
+        }
+    }
+
+    pub fn parse(content: &str) -> Result<Self, ConfigError> {
+        Ok(toml::from_str(content)?)
+    }
+
+    /// A config file listing every option with its default value and a comment.
+    pub fn template() -> String {
+        let defaults = toml::Table::try_from(Config::default())
+            .expect("The default config always serializes to a table");
+
+        let mut template = String::from("# on-exit-hook-waybar config, all options are optional\n");
+        for (key, doc, example) in TEMPLATE_DOCS {
+            // Options without a doc of their own belong to the one before.
+            if !doc.is_empty() {
+                template.push('\n');
+            }
+            for line in doc.lines() {
+                template.push_str(&format!("# {}\n", line.trim_start()));
+            }
+            let value = defaults.get(*key).filter(|value| match value {
+                toml::Value::Array(array) => !array.is_empty() || example.is_none(),
+                toml::Value::Table(table) => !table.is_empty() || example.is_none(),
+                _ => true,
+            });
+            match (value, example) {
+                (Some(value), _) => template.push_str(&format!("{} = {}\n", key, value)),
+                (None, Some(example)) if example.starts_with('[') => {
+                    for line in example.lines() {
+                        template.push_str(&format!("# {}\n", line));
+                    }
+                }
+                (None, Some(example)) => template.push_str(&format!("# {} = {}\n", key, example)),
+                (None, None) => {}
+            }
+        }
+        template
+    }
+
+    /// Checks the values beyond what parsing already does, reporting every problem at once.
+    pub fn validate(&self) -> Result<(), Vec<ConfigError>> {
+        let mut problems = Vec::new();
+
+        let offsets = std::iter::once((None, self.signal_offset)).chain(
+            self.profiles
+                .iter()
+                .filter_map(|p| p.signal_offset.map(|offset| (Some(p.name.clone()), offset))),
+        );
+        for (profile, offset) in offsets {
+            if let Err(source) = calculate_signal_number(offset) {
+                problems.push(ConfigError::SignalOffset { profile, source });
+            }
+        }
+
+        if self.medium_urgency_threshold > self.high_urgency_threshold {
+            problems.push(ConfigError::ThresholdOrder {
+                medium: self.medium_urgency_threshold,
+                high: self.high_urgency_threshold,
+            });
+        }
+
+        if StrftimeItems::new(&self.due_format).any(|item| item == Item::Error) {
+            problems.push(ConfigError::DueFormat(self.due_format.clone()));
+        }
+
+        let mut tag_colors: Vec<_> = self.tag_colors.iter().collect();
+        tag_colors.sort();
+        for (tag, color) in tag_colors {
+            if !is_valid_hex_color(color) {
+                problems.push(ConfigError::TagColor {
+                    tag: tag.clone(),
+                    color: color.clone(),
+                });
+            }
+        }
+
+        if let Err(e) = self.date_locale() {
+            problems.push(e);
+        }
+        if let Err(e) = self.process_matcher() {
+            problems.push(e);
+        }
+
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(problems)
+        }
+    }
+
+    /// Expands `~` and environment variables in all path fields.
+    pub fn resolve(mut self) -> Self {
+        self.output = self.output.as_deref().map(expand_path);
+        self.log_file = self.log_file.as_deref().map(expand_path);
+        self.waybar_config = self.waybar_config.as_deref().map(expand_path);
+        for extra in &mut self.extra_outputs {
+            extra.output = expand_path(&extra.output);
+        }
+        for profile in &mut self.profiles {
+            profile.output = profile.output.as_deref().map(expand_path);
+        }
+        self
+    }
+
+    pub fn output_path(&self, cache_dir: &Path) -> PathBuf {
+        self.output
+            .clone()
+            .unwrap_or_else(|| cache_dir.join(DEFAULT_OUTPUT_FILE_NAME))
+    }
+
+    pub fn log_file_path(&self, cache_dir: &Path) -> PathBuf {
+        self.log_file
+            .clone()
+            .unwrap_or_else(|| cache_dir.join(DEFAULT_LOG_FILE_NAME))
+    }
+
+    pub fn mode_file_path(cache_dir: &Path) -> PathBuf {
+        cache_dir.join(MODE_FILE_NAME)
+    }
+
+    /// Switches to the [`DisplayMode`] in the mode file at `path`. Without a mode file the
+    /// config is used as is, and a file with anything else in it is logged and ignored.
+    pub fn apply_mode_file(&mut self, path: &Path) {
+        let content = match fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == ErrorKind::NotFound => return,
+            Err(e) => {
+                warn!("Cannot read mode file {}: {}", path.display(), e);
+                return;
+            }
+        };
+        match content.trim() {
+            "compact" => self.apply_mode(DisplayMode::Compact),
+            "full" => self.apply_mode(DisplayMode::Full),
+            other => warn!(
+                "Unknown mode {:?} in {}, expected \"compact\" or \"full\"",
+                other,
+                path.display()
+            ),
+        }
+    }
+
+    pub fn apply_mode(&mut self, mode: DisplayMode) {
+        let full = mode == DisplayMode::Full;
+        self.show_count = full;
+        self.show_next = full;
+    }
+
+    pub fn tag_color(&self, tag: &str) -> Option<&str> {
+        self.tag_colors
+            .get(tag)
+            .or_else(|| self.tag_colors.get(&format!("+{}", tag)))
+            .map(String::as_str)
+            .filter(|color| is_valid_hex_color(color))
+    }
+
+    /// Builds the matcher for the waybar process, compiling `process_pattern` for regex matching.
+    pub fn process_matcher(&self) -> Result<ProcessMatcher, ConfigError> {
+        Ok(match self.process_match {
+            ProcessMatch::Comm => ProcessMatcher::Comm(self.process_names.clone()),
+            ProcessMatch::Cmdline => ProcessMatcher::Cmdline(self.process_names.clone()),
+            ProcessMatch::Regex => {
+                let pattern = self
+                    .process_pattern
+                    .as_deref()
+                    .ok_or(ConfigError::MissingPattern)?;
+                ProcessMatcher::Regex(regex::Regex::new(pattern)?)
+            }
+        })
+    }
+
+    /// The locale for formatting dates. Encoding suffixes like `.UTF-8` are ignored, so
+    /// the value of `$LANG` works as is.
+    pub fn date_locale(&self) -> Result<Locale, ConfigError> {
+        match self.locale.as_deref() {
+            None => Ok(Locale::POSIX),
+            Some(name) => {
+                let name = name.split('.').next().unwrap_or_default();
+                Locale::try_from(name).map_err(|_| ConfigError::UnknownLocale(name.to_string()))
+            }
+        }
+    }
+
+    /// Whether the hook should run for the taskwarrior `command` that triggered it.
+    /// Runs without a command (e.g. started by hand) are never skipped.
+    pub fn runs_for_command(&self, command: Option<&str>) -> bool {
+        match command {
+            Some(command) if !self.only_on.is_empty() => {
+                self.only_on.iter().any(|only| only == command)
+            }
+            _ => true,
+        }
+    }
+
+    /// Returns a copy of this config with the named profile's settings applied on top.
+    pub fn with_profile(&self, name: &str) -> Result<Config, ConfigError> {
+        self.profiles
+            .iter()
+            .find(|p| p.name == name)
+            .map(|p| self.apply_profile(p))
+            .ok_or_else(|| ConfigError::UnknownProfile(name.to_string()))
+    }
+
+    /// Returns one config per configured profile, in the order they are defined.
+    pub fn all_profiles(&self) -> Result<Vec<Config>, ConfigError> {
+        if self.profiles.is_empty() {
+            return Err(ConfigError::NoProfiles);
+        }
+        Ok(self
+            .profiles
+            .iter()
+            .map(|p| self.apply_profile(p))
+            .collect())
+    }
+
+    fn apply_profile(&self, profile: &Profile) -> Config {
+        Config {
+            filter: profile
+                .filter
+                .clone()
+                .unwrap_or_else(|| self.filter.clone()),
+            output: profile.output.clone().or_else(|| self.output.clone()),
+            signal_offset: profile.signal_offset.unwrap_or(self.signal_offset),
+            profiles: Vec::new(),
+            ..self.clone()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PROFILES_CONFIG: &str = r#"
+        filter = "status:pending"
+        signal_offset = 8
+
+        [[profiles]]
+        name = "work"
+        filter = "status:pending project:work"
+        output = "/tmp/waybar-work.json"
+        signal_offset = 9
+
+        [[profiles]]
+        name = "personal"
+        filter = "status:pending -work"
+    "#;
+
+    #[test]
+    fn test_empty_config_is_default() {
+        assert_eq!(Config::parse("").unwrap(), Config::default());
+    }
+
+    #[test]
+    fn test_template_parses_to_default() {
+        assert_eq!(
+            Config::parse(&Config::template()).unwrap(),
+            Config::default()
+        );
+    }
+
+    #[test]
+    fn test_template_documents_every_option() {
+        let defaults = toml::Table::try_from(Config::default()).unwrap();
+        for key in defaults.keys() {
+            assert!(
+                TEMPLATE_DOCS.iter().any(|(doc_key, _, _)| doc_key == key),
+                "{} is missing from TEMPLATE_DOCS",
+                key
+            );
+        }
+
+        // Uncommenting an example must give a valid config.
+        for (key, _, example) in TEMPLATE_DOCS {
+            match example {
+                Some(example) if example.starts_with('[') => {
+                    Config::parse(example).unwrap();
+                }
+                Some(example) => {
+                    Config::parse(&format!("{} = {}", key, example)).unwrap();
+                }
+                None => assert!(defaults.contains_key(*key), "{} needs an example", key),
+            }
+        }
+    }
+
+    #[test]
+    fn test_field_order() {
+        let config = Config::parse(r#"field_order = ["due", "description"]"#).unwrap();
+        assert_eq!(config.field_order, [Field::Due, Field::Description]);
+
+        let error = Config::parse(r#"field_order = ["due", "project"]"#).unwrap_err();
+        assert!(error.to_string().contains("unknown variant"), "{}", error);
+    }
+
+    #[test]
+    fn test_text_template_is_parsed_on_load() {
+        let config =
+            Config::parse(r#"text_template = "{#if due}{due} {/if}{description}""#).unwrap();
+        assert_eq!(
+            config.text_template,
+            Some(Template::parse("{#if due}{due} {/if}{description}").unwrap())
+        );
+
+        let error = Config::parse(r#"text_template = "{title}""#).unwrap_err();
+        assert!(
+            error.to_string().contains("unknown placeholder {title}"),
+            "{}",
+            error
+        );
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(Config::default().validate().is_ok());
+
+        let config = Config::parse(
+            r#"
+            signal_offset = 0
+            high_urgency_threshold = 4.0
+            medium_urgency_threshold = 6.0
+            "#,
+        )
+        .unwrap();
+        let problems = config.validate().unwrap_err();
+        assert_eq!(problems.len(), 2, "{:?}", problems);
+        assert!(matches!(
+            problems[0],
+            ConfigError::SignalOffset { profile: None, .. }
+        ));
+        assert!(matches!(problems[1], ConfigError::ThresholdOrder { .. }));
+
+        let config = Config::parse(
+            r#"
+            due_format = "%Y-%Q"
+            tag_colors = { "+urgent" = "red" }
+
+            [[profiles]]
+            name = "work"
+            signal_offset = 100
+            "#,
+        )
+        .unwrap();
+        let problems: Vec<String> = config
+            .validate()
+            .unwrap_err()
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        assert_eq!(problems.len(), 3, "{:?}", problems);
+        assert!(problems[0].starts_with("Invalid signal_offset in profile work"));
+        assert!(problems[1].starts_with("Invalid due_format"));
+        assert!(problems[2].starts_with("Invalid color \"red\" for tag +urgent"));
+    }
+
+    #[test]
+    fn test_runs_for_command() {
+        assert!(Config::default().runs_for_command(Some("list")));
+
+        let config = Config::parse(r#"only_on = ["add", "modify", "done"]"#).unwrap();
+        assert!(config.runs_for_command(Some("add")));
+        assert!(!config.runs_for_command(Some("list")));
+        assert!(config.runs_for_command(None));
+    }
+
+    #[test]
+    fn test_process_matcher() {
+        assert!(matches!(
+            Config::default().process_matcher(),
+            Ok(ProcessMatcher::Comm(names)) if names == ["waybar"]
+        ));
+
+        let config = Config::parse(r#"process_match = "cmdline""#).unwrap();
+        assert!(matches!(
+            config.process_matcher(),
+            Ok(ProcessMatcher::Cmdline(names)) if names == ["waybar"]
+        ));
+
+        let config = Config::parse(r#"process_match = "regex""#).unwrap();
+        assert!(matches!(
+            config.process_matcher(),
+            Err(ConfigError::MissingPattern)
+        ));
+
+        let config = Config::parse(
+            r#"
+            process_match = "regex"
+            process_pattern = "waybar-(wrapper"
+            "#,
+        )
+        .unwrap();
+        assert!(matches!(
+            config.process_matcher(),
+            Err(ConfigError::InvalidPattern(_))
+        ));
+    }
+
+    #[test]
+    fn test_process_names_one_or_many() {
+        let config = Config::parse(r#"process_names = "waybar-hypr""#).unwrap();
+        assert_eq!(config.process_names, ["waybar-hypr"]);
+
+        let config = Config::parse(r#"process_names = ["waybar", "waybar-hypr"]"#).unwrap();
+        assert_eq!(config.process_names, ["waybar", "waybar-hypr"]);
+
+        let config = Config::parse(r#"process_name = "waybar-hypr""#).unwrap();
+        assert_eq!(config.process_names, ["waybar-hypr"]);
+
+        assert!(Config::parse("process_names = 8").is_err());
+    }
+
+    #[test]
+    fn test_date_locale() {
+        assert_eq!(Config::default().date_locale().unwrap(), Locale::POSIX);
+
+        let config = Config::parse(r#"locale = "de_DE.UTF-8""#).unwrap();
+        assert_eq!(config.date_locale().unwrap(), Locale::de_DE);
+
+        let config = Config::parse(r#"locale = "xx_XX""#).unwrap();
+        assert!(matches!(
+            config.date_locale(),
+            Err(ConfigError::UnknownLocale(name)) if name == "xx_XX"
+        ));
+    }
+
+    #[test]
+    fn test_resolve_named_profile() {
+        let config = Config::parse(PROFILES_CONFIG).unwrap();
+
+        let work = config.with_profile("work").unwrap();
+        assert_eq!(work.filter, "status:pending project:work");
+        assert_eq!(work.output, Some(PathBuf::from("/tmp/waybar-work.json")));
+        assert_eq!(work.signal_offset, 9);
+
+        let personal = config.with_profile("personal").unwrap();
+        assert_eq!(personal.filter, "status:pending -work");
+        assert_eq!(personal.output, None);
+        assert_eq!(personal.signal_offset, 8);
+
+        assert!(matches!(
+            config.with_profile("missing"),
+            Err(ConfigError::UnknownProfile(name)) if name == "missing"
+        ));
+    }
+
+    #[test]
+    fn test_all_profiles_keeps_order() {
+        let config = Config::parse(PROFILES_CONFIG).unwrap();
+        let filters: Vec<String> = config
+            .all_profiles()
+            .unwrap()
+            .into_iter()
+            .map(|c| c.filter)
+            .collect();
+        assert_eq!(
+            filters,
+            ["status:pending project:work", "status:pending -work"]
+        );
+
+        assert!(matches!(
+            Config::default().all_profiles(),
+            Err(ConfigError::NoProfiles)
+        ));
+    }
+
+    #[test]
+    fn test_apply_mode_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = Config::mode_file_path(dir.path());
+        let configured = Config {
+            show_count: true,
+            ..Config::default()
+        };
+
+        let mut config = configured.clone();
+        config.apply_mode_file(&path);
+        assert_eq!(config, configured);
+
+        fs::write(&path, "full\n").unwrap();
+        config.apply_mode_file(&path);
+        assert!(config.show_count && config.show_next);
+
+        fs::write(&path, "compact").unwrap();
+        config.apply_mode_file(&path);
+        assert!(!config.show_count && !config.show_next);
+
+        fs::write(&path, "huge").unwrap();
+        config.apply_mode_file(&path);
+        assert!(!config.show_count && !config.show_next);
+    }
+}
@@ -1,3 +1,4 @@
+use std::path::{Path, PathBuf};
 use thiserror::Error;
 
 #[derive(Error, Debug)]
@@ -7,14 +8,51 @@ pub enum TaskHookWaybarError {
     SetLogger(#[from] log::SetLoggerError),
     #[error("File error: {0}")]
     File(#[from] std::io::Error),
+    #[error("File error at {}: {source}", path.display())]
+    FileWithPath {
+        path: PathBuf,
+        source: std::io::Error,
+    },
     #[error("Error: No processes found")]
     ProcessNotFound,
-    #[error("Process error: {0}")]
-    Proc(#[from] procfs::ProcError),
+    #[error("Cannot list processes, reading /proc is needed to find waybar: {0}")]
+    ProcUnavailable(procfs::ProcError),
+    #[error("systemctl could not signal {unit}: {message}")]
+    Systemctl { unit: String, message: String },
     #[error("Signal out of bounds: {0}")]
     InvalidRTSignal(#[from] InvalidRTSignalError),
+    #[error("Cannot parse task export: {source}, near: {snippet}")]
+    ExportJson {
+        line: usize,
+        column: usize,
+        snippet: String,
+        source: serde_json::Error,
+    },
     #[error("Json processing error: {0}")]
     Json(#[from] serde_json::Error),
+    #[error("Config error: {0}")]
+    Config(#[from] ConfigError),
+    #[error("Refusing to remove {}, it is neither a copy of nor a link to this binary", .0.display())]
+    ForeignHook(PathBuf),
+    #[error("Invalid argument: {0}")]
+    InvalidArgument(String),
+    #[cfg(feature = "taskchampion")]
+    #[error("Cannot read the taskchampion replica: {0}")]
+    Replica(#[from] taskchampion::Error),
+}
+
+impl TaskHookWaybarError {
+    /// Returns a closure for `map_err` that attaches `path` to an io error.
+    pub fn with_path(path: &Path) -> impl FnOnce(std::io::Error) -> Self {
+        let path = path.to_path_buf();
+        move |source| TaskHookWaybarError::FileWithPath { path, source }
+    }
+
+    /// Whether stdout (or another writer without a path) was closed by its reader, like
+    /// `head` when piping `--json` into it.
+    pub fn is_broken_pipe(&self) -> bool {
+        matches!(self, TaskHookWaybarError::File(e) if e.kind() == std::io::ErrorKind::BrokenPipe)
+    }
 }
 
 #[derive(Error, Debug)]
@@ -24,3 +62,59 @@ pub enum InvalidRTSignalError {
     #[error("Signal above maximum: {context}")]
     AboveMaxError { context: String },
 }
+
+#[derive(Error, Debug)]
+pub enum ConfigError {
+    #[error("Failed to parse config: {0}")]
+    Parse(#[from] toml::de::Error),
+    #[error("Unknown profile: {0}")]
+    UnknownProfile(String),
+    #[error("No profiles configured")]
+    NoProfiles,
+    #[error("Invalid process_pattern: {0}")]
+    InvalidPattern(#[from] regex::Error),
+    #[error("process_match = \"regex\" needs a process_pattern")]
+    MissingPattern,
+    #[error("Unknown locale: {0}")]
+    UnknownLocale(String),
+    #[error("Invalid signal_offset{}: {source}", profile_context(.profile))]
+    SignalOffset {
+        profile: Option<String>,
+        source: InvalidRTSignalError,
+    },
+    #[error("medium_urgency_threshold ({medium}) is above high_urgency_threshold ({high})")]
+    ThresholdOrder { medium: f64, high: f64 },
+    #[error("Invalid due_format {0:?}")]
+    DueFormat(String),
+    #[error("Invalid color {color:?} for tag {tag}, expected #rgb, #rrggbb or #rrggbbaa")]
+    TagColor { tag: String, color: String },
+}
+
+#[derive(Error, Debug, PartialEq)]
+pub enum TemplateError {
+    #[error("unknown placeholder {{{0}}}")]
+    UnknownPlaceholder(String),
+    #[error("unknown condition {0:?}")]
+    UnknownCondition(String),
+    #[error("{{/if}} without a matching {{#if}}")]
+    UnmatchedEnd,
+    #[error("{{#if {0}}} is never closed with {{/if}}")]
+    Unclosed(String),
+    #[error("a {{ is never closed, write {{{{ for a literal one")]
+    UnclosedBrace,
+}
+
+fn profile_context(profile: &Option<String>) -> String {
+    profile
+        .as_ref()
+        .map(|name| format!(" in profile {}", name))
+        .unwrap_or_default()
+}
+
+#[derive(Error, Debug)]
+pub enum InvalidDateError {
+    #[error("Expected a date like 20241206T143002Z, got {0:?}")]
+    Format(String),
+    #[error("Invalid date: {0}")]
+    Parse(#[from] chrono::ParseError),
+}
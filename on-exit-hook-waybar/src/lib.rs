@@ -0,0 +1,14 @@
+pub mod cli;
+pub mod config;
+pub mod errors;
+pub mod output;
+pub mod pango;
+#[cfg(feature = "taskchampion")]
+pub mod replica;
+pub mod signals;
+pub mod task;
+pub mod task_colors;
+pub mod template;
+pub mod urgency;
+pub mod utils;
+pub mod waybar_config;
@@ -1,47 +1,479 @@
-mod errors;
-mod signals;
-mod task;
-mod utils;
-
-use crate::errors::TaskHookWaybarError;
-use crate::signals::*;
-use crate::task::write_waybar_json;
-use crate::utils::setup_logging;
-use log::{error, info};
-use std::path::PathBuf;
-use task::generate_waybar_output_from_task_export;
+use log::{error, info, warn};
+use on_exit_hook_waybar::cli::{Args, TemplateTarget};
+use on_exit_hook_waybar::config::{Config, SignalBackend, PALETTE_FILE_NAME};
+use on_exit_hook_waybar::errors::TaskHookWaybarError;
+use on_exit_hook_waybar::output::{update_output, FileSink, OutputSink, StdoutSink, TeeSink};
+use on_exit_hook_waybar::pango::Palette;
+use on_exit_hook_waybar::signals::*;
+use on_exit_hook_waybar::task::{
+    advance_rotation, call_waiting_export, default_task_source, diff_tasks,
+    generate_waybar_output_with_waiting, load_tasks, read_previous_urgency_class, read_rotation,
+    read_snapshot, snapshot_tasks, write_extra_outputs, write_snapshot, Task,
+};
+use on_exit_hook_waybar::task_colors::TaskColors;
+use on_exit_hook_waybar::utils::{
+    default_hooks_dir, file_age, resolve_cache_dir, setup_logging, tail_lines, uninstall_hook,
+    QuietWriter, RunLock, Timings,
+};
+use on_exit_hook_waybar::waybar_config::{default_waybar_config_path, read_module_signal};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+/// How long a run waits for an overlapping one to finish writing and signalling.
+const LOCK_TIMEOUT: Duration = Duration::from_secs(2);
+const LOCK_SUFFIX: &str = ".lock";
+/// The tasks of the previous run for `log_changes`.
+const SNAPSHOT_SUFFIX: &str = ".snapshot";
+/// The task `rotate_top` showed last.
+const ROTATION_SUFFIX: &str = ".rotation";
 
 fn main() {
-    let cache_dir = dirs::cache_dir().unwrap_or_else(|| {
-        eprintln!("Failed to determine cache directory");
+    let cache_dir = resolve_cache_dir();
+
+    let args = Args::parse(std::env::args().skip(1)).unwrap_or_else(|e| {
+        eprintln!("{}", e);
         std::process::exit(1)
     });
 
-    if let Err(e) = setup_logging(&cache_dir.join("waybar-task-hook.log")) {
+    // Before loading the config, so it also helps when the current one is broken.
+    if let Some(target) = &args.export_config_template {
+        let template = Config::template();
+        match target {
+            TemplateTarget::Stdout => print!("{}", template),
+            TemplateTarget::File(path) => {
+                if let Err(e) = std::fs::write(path, template) {
+                    eprintln!("{}", TaskHookWaybarError::with_path(path)(e));
+                    std::process::exit(1)
+                }
+                println!("Wrote config template to {}", path.display());
+            }
+        }
+        return;
+    }
+
+    if args.uninstall {
+        if let Err(e) = uninstall(args.hooks_dir.clone()) {
+            eprintln!("{}", e);
+            std::process::exit(1)
+        }
+        return;
+    }
+
+    let mut config = load_config(&args)
+        .unwrap_or_else(|e| {
+            eprintln!("Failed to load config: {}", e);
+            std::process::exit(1)
+        })
+        .resolve();
+
+    if args.validate_config {
+        match config.validate() {
+            Ok(()) => println!("Config is valid"),
+            Err(problems) => {
+                problems.iter().for_each(|problem| eprintln!("{}", problem));
+                std::process::exit(1)
+            }
+        }
+        return;
+    }
+
+    // Before setting up logging, which starts a new log file.
+    if let Some(count) = args.log_tail {
+        let log_file_path = config.log_file_path(&cache_dir);
+        match tail_lines(&log_file_path, count) {
+            Ok(lines) => lines.iter().for_each(|line| println!("{}", line)),
+            Err(e) => {
+                eprintln!("No log to show: {}", e);
+                std::process::exit(1)
+            }
+        }
+        return;
+    }
+
+    if let Err(e) = setup_logging(&config.log_file_path(&cache_dir)) {
         eprintln!("Failed to initialize logging: {}", e);
         std::process::exit(1);
     }
 
-    if let Err(e) = run(&cache_dir.join("waybar-tasks.json")) {
+    if let Some(path) = palette_path(&args) {
+        config.palette = Palette::load(&path);
+    }
+    if config.pango_markup && config.taskwarrior_colors {
+        match TaskColors::load() {
+            Ok(colors) => config.task_colors = colors,
+            Err(e) => warn!("Cannot read taskwarrior's colors, using the palette: {}", e),
+        }
+    }
+
+    if let Err(e) = config.date_locale() {
+        warn!("{}, formatting dates in the C locale", e);
+    }
+
+    let process_matcher = config.process_matcher().unwrap_or_else(|e| {
+        error!("{}", e);
+        eprintln!("{}", e);
+        std::process::exit(1)
+    });
+
+    if args.check {
+        match check_waybar(&ProcfsBackend, &process_matcher) {
+            Ok(check) => {
+                print!("{}", check);
+                std::process::exit(if check.found() { 0 } else { 1 })
+            }
+            Err(e) => {
+                error!("{:?}", e);
+                eprintln!("{:?}", e);
+                std::process::exit(1)
+            }
+        }
+    }
+
+    if args.list_waybar {
+        match list_waybar_processes(&ProcfsBackend, &process_matcher) {
+            Ok(listing) => print!("{}", listing),
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(1)
+            }
+        }
+        return;
+    }
+
+    if !config.runs_for_command(args.task_command.as_deref()) {
+        info!(
+            "Skipping export for command {}",
+            args.task_command.as_deref().unwrap_or_default()
+        );
+        return;
+    }
+
+    let mut configs = select_configs(&config, &args).unwrap_or_else(|e| {
         error!("{:?}", e);
-        eprintln!("{:?}", e);
+        eprintln!("{}", e);
+        std::process::exit(1)
+    });
+
+    for config in configs.iter_mut().filter(|config| config.auto_signal) {
+        apply_auto_signal(config, &config.output_path(&cache_dir));
+    }
+
+    if args.print_signal {
+        for config in &configs {
+            match format_signal_mapping(config.signal_offset) {
+                Ok(mapping) => println!("{}", mapping),
+                Err(e) => {
+                    eprintln!("{}", e);
+                    std::process::exit(1)
+                }
+            }
+        }
+        return;
+    }
+
+    if args.check_stale {
+        let all_fresh = configs
+            .iter()
+            .all(|config| check_stale(config, &config.output_path(&cache_dir)));
+        std::process::exit(if all_fresh { 0 } else { 1 });
+    }
+
+    let to_stdout = args.json || args.dry_run;
+    if config.skip_if_no_waybar && !to_stdout && no_waybar_running(&ProcfsBackend, &process_matcher)
+    {
+        info!("No waybar running, skipping export");
+        return;
+    }
+    let mut stdout = QuietWriter::new(std::io::stdout(), args.quiet);
+    let mut failed = false;
+    for config in &mut configs {
+        let output_path = config.output_path(&cache_dir);
+        config.apply_mode_file(&Config::mode_file_path(&cache_dir));
+        if let Some(top) = config.rotate_top {
+            let rotation_path = sibling_path(&output_path, ROTATION_SUFFIX);
+            // Only runs that update the bar move the rotation on.
+            config.rotation_index = if to_stdout {
+                read_rotation(&rotation_path).unwrap_or_default()
+            } else {
+                advance_rotation(&rotation_path, top).unwrap_or_else(|e| {
+                    warn!("Cannot keep the rotation for the next run: {}", e);
+                    0
+                })
+            };
+        }
+        if config.class_hysteresis > 0.0 && !config.output_is_fifo {
+            config.previous_urgency_class = read_previous_urgency_class(&output_path);
+        }
+        let config = &*config;
+        let written_path = (!to_stdout).then(|| output_path.clone());
+        let mut sink: Box<dyn OutputSink> = if to_stdout {
+            if args.dry_run {
+                let message = format!(
+                    "Dry run, not writing {} and not sending SIGRTMIN+{}",
+                    output_path.display(),
+                    config.signal_offset
+                );
+                info!("{}", message);
+                eprintln!("{}", message);
+
+                let message = match config.signal_backend {
+                    SignalBackend::Kill => {
+                        dry_run_signal(&ProcfsBackend, &process_matcher, config.signal_offset)
+                            .unwrap_or_else(|e| e.to_string())
+                    }
+                    SignalBackend::Systemctl => format!(
+                        "Would signal the main process of {} with systemctl",
+                        config.systemd_unit
+                    ),
+                };
+                info!("{}", message);
+                eprintln!("{}", message);
+            }
+            Box::new(StdoutSink::new(config.output_target, config.pango_markup))
+        } else {
+            let file_sink = FileSink::new(output_path, config.pretty_output)
+                .with_fifo(config.output_is_fifo)
+                .with_target(config.output_target, config.pango_markup);
+            if args.tee {
+                Box::new(TeeSink::new(vec![
+                    Box::new(file_sink),
+                    Box::new(StdoutSink::new(config.output_target, config.pango_markup)),
+                ]))
+            } else {
+                Box::new(file_sink)
+            }
+        };
+
+        let signal = (!to_stdout).then_some(&process_matcher);
+        let mut timings = Timings::default();
+        if let Err(e) = run(
+            config,
+            sink.as_mut(),
+            signal,
+            written_path.as_deref(),
+            &mut stdout,
+            &mut timings,
+        ) {
+            // Whoever read stdout has all they wanted, so this is no failure.
+            if e.is_broken_pipe() {
+                info!("Stdout was closed, stopping");
+                return;
+            }
+            error!("{:?}", e);
+            eprintln!("{:?}", e);
+            failed = true;
+        }
+        if args.timings {
+            eprint!("{}", timings.summary());
+        }
+    }
+    if failed {
         std::process::exit(1);
     }
-    println!("Exported to waybar.");
+    if !to_stdout {
+        let _ = writeln!(stdout, "Exported to waybar.");
+    }
     info!("Export done")
 }
 
-fn run(waybar_json_path: &PathBuf) -> Result<(), TaskHookWaybarError> {
-    const PROCESS_NAME: &str = "waybar";
-    const SIGNAL_OFFSET: i32 = 8;
+fn load_config(args: &Args) -> Result<Config, TaskHookWaybarError> {
+    if let Some(path) = &args.config_path {
+        let content =
+            std::fs::read_to_string(path).map_err(TaskHookWaybarError::with_path(path))?;
+        return Ok(Config::parse(&content)?);
+    }
+    match Config::default_path() {
+        Some(path) => Config::load(&path),
+        None => Ok(Config::default()),
+    }
+}
+
+/// The palette lives next to the config file.
+fn palette_path(args: &Args) -> Option<PathBuf> {
+    let config_dir = match &args.config_path {
+        Some(path) => path.parent().map(Path::to_path_buf),
+        None => Config::default_dir(),
+    };
+    config_dir.map(|dir| dir.join(PALETTE_FILE_NAME))
+}
+
+/// Takes `signal_offset` from the waybar config, keeping the configured one if the module
+/// or its signal cannot be found.
+fn apply_auto_signal(config: &mut Config, output_path: &Path) {
+    let Some(path) = config
+        .waybar_config
+        .clone()
+        .or_else(default_waybar_config_path)
+    else {
+        warn!(
+            "No waybar config found for auto_signal, using SIGRTMIN+{}",
+            config.signal_offset
+        );
+        return;
+    };
+    match read_module_signal(&path, output_path) {
+        Ok(Some(offset)) => {
+            info!("Using SIGRTMIN+{} from {}", offset, path.display());
+            config.signal_offset = offset;
+        }
+        Ok(None) => warn!(
+            "No module showing {} with a signal in {}, using SIGRTMIN+{}",
+            output_path.display(),
+            path.display(),
+            config.signal_offset
+        ),
+        Err(e) => warn!(
+            "Cannot read the signal from {} ({}), using SIGRTMIN+{}",
+            path.display(),
+            e,
+            config.signal_offset
+        ),
+    }
+}
+
+/// Removes this binary from `hooks_dir`, by default taskwarrior's hooks directory.
+fn uninstall(hooks_dir: Option<PathBuf>) -> Result<(), TaskHookWaybarError> {
+    let binary = std::env::current_exe()?;
+    let hooks_dir = hooks_dir.or_else(default_hooks_dir).ok_or_else(|| {
+        TaskHookWaybarError::InvalidArgument("no home directory, pass the hooks directory".into())
+    })?;
+    let hook = hooks_dir.join(binary.file_name().unwrap_or_default());
+    uninstall_hook(&hook, &binary)?;
+    println!("Removed {}", hook.display());
+    Ok(())
+}
+
+/// Prints how old the output file is, returns false if it is stale or missing.
+fn check_stale(config: &Config, output_path: &Path) -> bool {
+    match file_age(output_path, SystemTime::now()) {
+        Ok(age) => {
+            let minutes = age.as_secs() / 60;
+            let stale = minutes >= config.stale_after_minutes;
+            println!(
+                "{}: last updated {} minutes ago{}",
+                output_path.display(),
+                minutes,
+                if stale { " (stale)" } else { "" }
+            );
+            !stale
+        }
+        Err(e) => {
+            println!("{}", e);
+            false
+        }
+    }
+}
+
+fn select_configs(config: &Config, args: &Args) -> Result<Vec<Config>, TaskHookWaybarError> {
+    if args.all_profiles {
+        Ok(config.all_profiles()?)
+    } else if let Some(name) = &args.profile {
+        Ok(vec![config.with_profile(name)?])
+    } else {
+        Ok(vec![config.clone()])
+    }
+}
+
+/// Logs how the tasks changed since the previous run and keeps them for the next one.
+fn log_changes(tasks: &[Task], snapshot_path: &Path) {
+    let snapshot = snapshot_tasks(tasks);
+    match read_snapshot(snapshot_path) {
+        Some(previous) => info!(
+            "Since the last export: {}",
+            diff_tasks(&previous, &snapshot)
+        ),
+        None => info!("No previous export to compare with"),
+    }
+    if let Err(e) = write_snapshot(snapshot_path, &snapshot) {
+        warn!("Cannot keep the tasks for the next comparison: {}", e);
+    }
+}
+
+/// A file next to `output_path`, like its lock file.
+fn sibling_path(output_path: &Path, suffix: &str) -> PathBuf {
+    let mut path = output_path.as_os_str().to_owned();
+    path.push(suffix);
+    path.into()
+}
+
+/// Exports and writes the tasks, then signals the processes of `signal` if given. When the
+/// sink writes to `output_path`, writing and signalling wait for overlapping runs. Debug
+/// builds also print the output to `stdout`.
+#[cfg_attr(not(debug_assertions), allow(unused_variables))]
+fn run(
+    config: &Config,
+    sink: &mut dyn OutputSink,
+    signal: Option<&ProcessMatcher>,
+    output_path: Option<&Path>,
+    stdout: &mut dyn Write,
+    timings: &mut Timings,
+) -> Result<(), TaskHookWaybarError> {
+    let tasks = timings.time("task export", || {
+        load_tasks(default_task_source().as_deref(), config)
+    })?;
+    let waiting = if config.show_waiting_section {
+        timings.time("waiting export", || call_waiting_export(config))?
+    } else {
+        Vec::new()
+    };
+    let waybar_output = timings.time("render", || {
+        generate_waybar_output_with_waiting(&tasks, &waiting, config)
+    });
+    let lock_path = output_path.map(|path| sibling_path(path, LOCK_SUFFIX));
+    let _lock = match &lock_path {
+        Some(path) => match timings.time("lock", || RunLock::acquire(path, LOCK_TIMEOUT))? {
+            Some(lock) => Some(lock),
+            None => {
+                warn!(
+                    "Another run still holds {} after {:?}, skipping this one",
+                    path.display(),
+                    LOCK_TIMEOUT
+                );
+                return Ok(());
+            }
+        },
+        None => None,
+    };
+    if let Some(path) = output_path.filter(|_| config.log_changes) {
+        log_changes(&tasks, &sibling_path(path, SNAPSHOT_SUFFIX));
+    }
+    let changed = timings.time("write", || {
+        update_output(
+            sink,
+            &waybar_output,
+            config.skip_unchanged,
+            config.always_signal,
+        )
+    })?;
+    // Not for --json and --dry-run, which only print the main output.
+    if signal.is_some() && !config.extra_outputs.is_empty() {
+        timings.time("extra outputs", || {
+            write_extra_outputs(&tasks, &waiting, config)
+        })?;
+    }
 
-    let waybar_output = generate_waybar_output_from_task_export()?;
-    write_waybar_json(&waybar_output, waybar_json_path)?;
+    let Some(process_matcher) = signal.filter(|_| changed) else {
+        return Ok(());
+    };
 
     #[cfg(debug_assertions)]
-    crate::task::debug::print_output(&waybar_output)?;
+    on_exit_hook_waybar::output::debug::print_output(stdout, &waybar_output)?;
 
-    send_offset_signal_to_process_by_name(PROCESS_NAME, SIGNAL_OFFSET)?;
+    timings.time("signal", || match config.signal_backend {
+        SignalBackend::Kill => signal_waybar(
+            &ProcfsBackend,
+            process_matcher,
+            config.signal_offset,
+            config.strict_signal,
+            config.verify_before_signal,
+        ),
+        SignalBackend::Systemctl => signal_systemd_unit(
+            &config.systemd_unit,
+            config.signal_offset,
+            config.strict_signal,
+        ),
+    })?;
     info!("Success sending");
     Ok(())
 }
@@ -0,0 +1,698 @@
+use crate::config::OutputTarget;
+use crate::errors::TaskHookWaybarError;
+use crate::pango::strip_markup;
+use crate::utils::{ensure_writable_dir, truncate_chars};
+use log::{info, warn};
+use serde::{Serialize, Serializer};
+use std::os::unix::fs::{FileTypeExt, OpenOptionsExt};
+use std::os::unix::io::AsRawFd;
+use std::{
+    fs::OpenOptions,
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+/// The json waybar reads for a custom module with `"return-type": "json"`.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct WaybarOutput {
+    pub(crate) text: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) tooltip: Option<String>,
+    #[serde(
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "serialize_class"
+    )]
+    pub(crate) class: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) percentage: Option<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) alt: Option<String>,
+}
+
+/// Waybar takes a string as one single class, so the space-separated list is
+/// written as an array of classes.
+fn serialize_class<S: Serializer>(
+    class: &Option<String>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.collect_seq(class.iter().flat_map(|c| c.split_whitespace()))
+}
+
+/// An empty tooltip is left out so waybar does not show an empty box.
+pub(crate) fn non_empty_tooltip(tooltip: String) -> Option<String> {
+    (!tooltip.is_empty()).then_some(tooltip)
+}
+
+/// Characters of the bar text i3status-rust falls back to when the bar is crowded.
+const SHORT_TEXT_LEN: usize = 20;
+
+/// The json of an i3status-rust custom block with `json = true`. It has no tooltip, and the
+/// urgency class becomes the block's state.
+#[derive(Serialize, Debug, PartialEq)]
+struct I3statusBlock<'a> {
+    text: &'a str,
+    short_text: String,
+    state: &'static str,
+}
+
+impl<'a> From<&'a WaybarOutput> for I3statusBlock<'a> {
+    fn from(output: &'a WaybarOutput) -> Self {
+        let has_class = |name| {
+            output
+                .class
+                .as_deref()
+                .is_some_and(|class| class.split_whitespace().any(|c| c == name))
+        };
+        let state = if output.class.is_none() {
+            "Idle"
+        } else if has_class("urgency-high") {
+            "Critical"
+        } else if has_class("urgency-medium") {
+            "Warning"
+        } else {
+            "Info"
+        };
+        I3statusBlock {
+            text: &output.text,
+            short_text: truncate_chars(&output.text, SHORT_TEXT_LEN),
+            state,
+        }
+    }
+}
+
+fn to_json<T: Serialize>(value: &T, pretty: bool) -> Result<String, serde_json::Error> {
+    if pretty {
+        serde_json::to_string_pretty(value)
+    } else {
+        serde_json::to_string(value)
+    }
+}
+
+/// The output in the format of `target`, without the trailing newline. `Plain` is the bar
+/// text alone, with its pango `markup` taken out. Without markup the text is kept as is, as
+/// descriptions were not escaped and a `<` in them is no tag.
+fn serialize_output(
+    output: &WaybarOutput,
+    target: OutputTarget,
+    markup: bool,
+    pretty: bool,
+) -> Result<String, serde_json::Error> {
+    match target {
+        OutputTarget::Waybar => to_json(output, pretty),
+        OutputTarget::I3statusRust => to_json(&I3statusBlock::from(output), pretty),
+        OutputTarget::Plain if markup => Ok(strip_markup(&output.text)),
+        OutputTarget::Plain => Ok(output.text.clone()),
+    }
+}
+
+pub fn write_waybar_json(
+    output: &WaybarOutput,
+    json_path: &Path,
+    pretty: bool,
+) -> Result<(), TaskHookWaybarError> {
+    write_output_file(output, json_path, OutputTarget::Waybar, false, pretty)
+}
+
+fn write_output_file(
+    output: &WaybarOutput,
+    json_path: &Path,
+    target: OutputTarget,
+    markup: bool,
+    pretty: bool,
+) -> Result<(), TaskHookWaybarError> {
+    if let Some(dir) = json_path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        ensure_writable_dir(dir)?;
+    }
+    let file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(json_path)
+        .map_err(TaskHookWaybarError::with_path(json_path))?;
+
+    info!("Opened file at {}", json_path.display());
+
+    let mut writer = BufWriter::new(file);
+    let json_output = serialize_output(output, target, markup, pretty)?;
+
+    writeln!(writer, "{}", json_output)
+        .and_then(|_| writer.flush())
+        .map_err(TaskHookWaybarError::with_path(json_path))?;
+
+    info!("Json written to file");
+
+    Ok(())
+}
+
+fn is_fifo(path: &Path) -> bool {
+    std::fs::metadata(path).is_ok_and(|metadata| metadata.file_type().is_fifo())
+}
+
+/// Writes the output to a FIFO without truncating or creating it.
+///
+/// The FIFO is opened non-blocking so the hook never hangs without a reader; in that
+/// case nothing is written and `false` is returned.
+fn write_waybar_fifo(
+    output: &WaybarOutput,
+    fifo_path: &Path,
+    target: OutputTarget,
+    markup: bool,
+    pretty: bool,
+) -> Result<bool, TaskHookWaybarError> {
+    let file = match OpenOptions::new()
+        .write(true)
+        .custom_flags(libc::O_NONBLOCK)
+        .open(fifo_path)
+    {
+        Ok(file) => file,
+        Err(e) if e.raw_os_error() == Some(libc::ENXIO) => {
+            warn!("No reader on FIFO {}, skipping write", fifo_path.display());
+            return Ok(false);
+        }
+        Err(e) => return Err(TaskHookWaybarError::with_path(fifo_path)(e)),
+    };
+
+    // Only the open has to be non-blocking, a reader is there now so let writes block
+    // instead of failing on a full pipe.
+    let fd = file.as_raw_fd();
+    unsafe {
+        let flags = libc::fcntl(fd, libc::F_GETFL);
+        libc::fcntl(fd, libc::F_SETFL, flags & !libc::O_NONBLOCK);
+    }
+
+    let json_output = serialize_output(output, target, markup, pretty)?;
+    let mut writer = BufWriter::new(file);
+    writeln!(writer, "{}", json_output)
+        .and_then(|_| writer.flush())
+        .map_err(TaskHookWaybarError::with_path(fifo_path))?;
+
+    info!("Json written to FIFO {}", fifo_path.display());
+    Ok(true)
+}
+
+/// Destination for the generated waybar output.
+pub trait OutputSink {
+    fn write(&mut self, output: &WaybarOutput) -> Result<(), TaskHookWaybarError>;
+
+    /// Whether the destination already holds exactly `output`.
+    fn is_current(&self, _output: &WaybarOutput) -> bool {
+        false
+    }
+}
+
+/// Writes `output` unless `skip_unchanged` is set and the sink already holds it. Returns
+/// whether waybar needs a signal: after a write, or always with `always_signal`.
+pub fn update_output(
+    sink: &mut dyn OutputSink,
+    output: &WaybarOutput,
+    skip_unchanged: bool,
+    always_signal: bool,
+) -> Result<bool, TaskHookWaybarError> {
+    if skip_unchanged && sink.is_current(output) {
+        info!("Output unchanged, not writing it again");
+        return Ok(always_signal);
+    }
+    sink.write(output)?;
+    Ok(true)
+}
+
+/// Writes the output to the file waybar reads, the default sink.
+pub struct FileSink {
+    path: PathBuf,
+    pretty: bool,
+    fifo: bool,
+    target: OutputTarget,
+    markup: bool,
+}
+
+impl FileSink {
+    pub fn new(path: PathBuf, pretty: bool) -> Self {
+        FileSink {
+            path,
+            pretty,
+            fifo: false,
+            target: OutputTarget::Waybar,
+            markup: false,
+        }
+    }
+
+    /// Writes in the format of another bar than waybar. `markup` tells whether the output
+    /// holds pango markup, which `Plain` takes out.
+    pub fn with_target(mut self, target: OutputTarget, markup: bool) -> Self {
+        self.target = target;
+        self.markup = markup;
+        self
+    }
+
+    /// Writes FIFO-safe if the path turns out to be a FIFO.
+    pub fn with_fifo(mut self, fifo: bool) -> Self {
+        self.fifo = fifo;
+        self
+    }
+}
+
+impl OutputSink for FileSink {
+    fn write(&mut self, output: &WaybarOutput) -> Result<(), TaskHookWaybarError> {
+        if self.fifo && is_fifo(&self.path) {
+            write_waybar_fifo(output, &self.path, self.target, self.markup, self.pretty).map(|_| ())
+        } else {
+            write_output_file(output, &self.path, self.target, self.markup, self.pretty)
+        }
+    }
+
+    /// A FIFO never is, reading it would take the output away from waybar.
+    fn is_current(&self, output: &WaybarOutput) -> bool {
+        if is_fifo(&self.path) {
+            return false;
+        }
+        let Ok(json_output) = serialize_output(output, self.target, self.markup, self.pretty)
+        else {
+            return false;
+        };
+        std::fs::read_to_string(&self.path)
+            .is_ok_and(|content| content.strip_suffix('\n') == Some(json_output.as_str()))
+    }
+}
+
+/// Prints the output to stdout, e.g. for piping it somewhere else.
+#[derive(Default)]
+pub struct StdoutSink {
+    target: OutputTarget,
+    markup: bool,
+}
+
+impl StdoutSink {
+    /// Prints in the format of `target`, `markup` as for [`FileSink::with_target`].
+    pub fn new(target: OutputTarget, markup: bool) -> Self {
+        StdoutSink { target, markup }
+    }
+}
+
+impl OutputSink for StdoutSink {
+    fn write(&mut self, output: &WaybarOutput) -> Result<(), TaskHookWaybarError> {
+        let json_output = serialize_output(output, self.target, self.markup, false)?;
+        write_line(&mut std::io::stdout().lock(), &json_output)
+    }
+}
+
+/// Writes the same output to several sinks, like `--tee` to the file and to stdout.
+pub struct TeeSink {
+    sinks: Vec<Box<dyn OutputSink>>,
+}
+
+impl TeeSink {
+    pub fn new(sinks: Vec<Box<dyn OutputSink>>) -> Self {
+        TeeSink { sinks }
+    }
+}
+
+impl OutputSink for TeeSink {
+    /// Stops at the first sink that fails.
+    fn write(&mut self, output: &WaybarOutput) -> Result<(), TaskHookWaybarError> {
+        self.sinks
+            .iter_mut()
+            .try_for_each(|sink| sink.write(output))
+    }
+
+    fn is_current(&self, output: &WaybarOutput) -> bool {
+        self.sinks.iter().all(|sink| sink.is_current(output))
+    }
+}
+
+/// Writes `line` to a stream without a path like stdout. A closed stream is reported as an
+/// error for which [`TaskHookWaybarError::is_broken_pipe`] holds, instead of panicking.
+fn write_line(out: &mut dyn Write, line: &str) -> Result<(), TaskHookWaybarError> {
+    writeln!(out, "{}", line).and_then(|_| out.flush())?;
+    Ok(())
+}
+
+#[cfg(debug_assertions)]
+pub mod debug {
+    use super::WaybarOutput;
+    use crate::errors::TaskHookWaybarError;
+    use std::io::Write;
+
+    pub fn print_output(
+        out: &mut dyn Write,
+        output: &WaybarOutput,
+    ) -> Result<(), TaskHookWaybarError> {
+        let json_output = serde_json::to_string_pretty(output)?;
+        super::write_line(out, &json_output)
+    }
+}
+
+/**************
+ * Unit tests *
+ **************/
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::task::{generate_waybar_output, Task};
+    use std::fs;
+    use std::os::unix::fs::PermissionsExt;
+
+    fn sample_output() -> WaybarOutput {
+        WaybarOutput {
+            text: "1 Test".to_string(),
+            tooltip: Some("1 Test".to_string()),
+            class: Some("urgency-low".to_string()),
+            percentage: None,
+            alt: None,
+        }
+    }
+
+    #[derive(Default)]
+    struct MemorySink {
+        outputs: Vec<WaybarOutput>,
+    }
+
+    impl OutputSink for MemorySink {
+        fn write(&mut self, output: &WaybarOutput) -> Result<(), TaskHookWaybarError> {
+            self.outputs.push(output.clone());
+            Ok(())
+        }
+
+        fn is_current(&self, output: &WaybarOutput) -> bool {
+            self.outputs.last() == Some(output)
+        }
+    }
+
+    #[test]
+    fn test_always_signal_unchanged_output() {
+        let output = sample_output();
+        let mut sink = MemorySink::default();
+
+        assert!(update_output(&mut sink, &output, true, false).unwrap());
+        assert!(!update_output(&mut sink, &output, true, false).unwrap());
+        assert!(update_output(&mut sink, &output, true, true).unwrap());
+        assert_eq!(sink.outputs.len(), 1);
+
+        assert!(update_output(&mut sink, &output, false, false).unwrap());
+        assert_eq!(sink.outputs.len(), 2);
+    }
+
+    /// A sink that shares what it received with the test after being boxed.
+    struct SharedSink(std::rc::Rc<std::cell::RefCell<MemorySink>>);
+
+    impl OutputSink for SharedSink {
+        fn write(&mut self, output: &WaybarOutput) -> Result<(), TaskHookWaybarError> {
+            self.0.borrow_mut().write(output)
+        }
+
+        fn is_current(&self, output: &WaybarOutput) -> bool {
+            self.0.borrow().is_current(output)
+        }
+    }
+
+    #[test]
+    fn test_tee_sink_writes_to_all() {
+        let file_like = std::rc::Rc::new(std::cell::RefCell::new(MemorySink::default()));
+        let stdout_like = std::rc::Rc::new(std::cell::RefCell::new(MemorySink::default()));
+        let mut tee = TeeSink::new(vec![
+            Box::new(SharedSink(file_like.clone())),
+            Box::new(SharedSink(stdout_like.clone())),
+        ]);
+        let output = sample_output();
+
+        assert!(!tee.is_current(&output));
+        tee.write(&output).unwrap();
+        assert_eq!(file_like.borrow().outputs.len(), 1);
+        assert_eq!(file_like.borrow().outputs[0], output);
+        assert_eq!(stdout_like.borrow().outputs, file_like.borrow().outputs);
+        assert!(tee.is_current(&output));
+
+        stdout_like.borrow_mut().outputs.clear();
+        assert!(!tee.is_current(&output));
+    }
+
+    #[test]
+    fn test_file_sink_is_current() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut sink = FileSink::new(dir.path().join("waybar-tasks.json"), false);
+        let output = sample_output();
+        assert!(!sink.is_current(&output));
+
+        sink.write(&output).unwrap();
+        assert!(sink.is_current(&output));
+        assert!(!sink.is_current(&WaybarOutput {
+            text: "2 Other".to_string(),
+            ..output
+        }));
+    }
+
+    #[test]
+    fn test_write_waybar_json_error_has_path() {
+        let json_path = PathBuf::from("/nonexistent/dir/waybar-tasks.json");
+        let result = write_waybar_json(&sample_output(), &json_path, false);
+
+        assert!(matches!(
+            result,
+            Err(TaskHookWaybarError::FileWithPath { path, .. }) if path == json_path
+        ));
+    }
+
+    #[test]
+    fn test_write_waybar_json_to_read_only_dir() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::set_permissions(dir.path(), fs::Permissions::from_mode(0o555)).unwrap();
+        let json_path = dir.path().join("waybar-tasks.json");
+
+        let result = write_waybar_json(&sample_output(), &json_path, false);
+        fs::set_permissions(dir.path(), fs::Permissions::from_mode(0o755)).unwrap();
+
+        match result {
+            Err(error @ TaskHookWaybarError::FileWithPath { .. }) => {
+                let message = error.to_string();
+                assert!(message.contains(&dir.path().display().to_string()));
+                assert!(message.contains("not writable"), "{}", message);
+            }
+            other => panic!("Expected FileWithPath, got {:?}", other),
+        }
+        assert!(!json_path.exists());
+    }
+
+    #[test]
+    fn test_output_sinks() {
+        let waybar_output = sample_output();
+
+        let mut memory_sink = MemorySink::default();
+        let sink: &mut dyn OutputSink = &mut memory_sink;
+        sink.write(&waybar_output).unwrap();
+        assert_eq!(memory_sink.outputs.len(), 1);
+        assert_eq!(memory_sink.outputs[0], waybar_output);
+
+        let dir = tempfile::tempdir().unwrap();
+        let json_path = dir.path().join("waybar-tasks.json");
+        FileSink::new(json_path.clone(), false)
+            .write(&waybar_output)
+            .unwrap();
+        assert_eq!(
+            std::fs::read_to_string(json_path).unwrap(),
+            format!("{}\n", serde_json::to_string(&waybar_output).unwrap())
+        );
+    }
+
+    #[test]
+    fn test_pretty_and_compact_output() {
+        let waybar_output = sample_output();
+
+        let dir = tempfile::tempdir().unwrap();
+        let pretty_path = dir.path().join("pretty.json");
+        let compact_path = dir.path().join("compact.json");
+        write_waybar_json(&waybar_output, &pretty_path, true).unwrap();
+        write_waybar_json(&waybar_output, &compact_path, false).unwrap();
+
+        let pretty = std::fs::read_to_string(pretty_path).unwrap();
+        let compact = std::fs::read_to_string(compact_path).unwrap();
+        assert!(pretty.trim_end().contains('\n'));
+        assert!(!compact.trim_end().contains('\n'));
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(&pretty).unwrap(),
+            serde_json::from_str::<serde_json::Value>(&compact).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_write_waybar_fifo() {
+        let dir = tempfile::tempdir().unwrap();
+        let fifo_path = dir.path().join("waybar-tasks.fifo");
+        let c_path = std::ffi::CString::new(fifo_path.to_str().unwrap()).unwrap();
+        assert_eq!(unsafe { libc::mkfifo(c_path.as_ptr(), 0o600) }, 0);
+        assert!(is_fifo(&fifo_path));
+
+        let waybar_output = sample_output();
+        assert!(!write_waybar_fifo(
+            &waybar_output,
+            &fifo_path,
+            OutputTarget::Waybar,
+            false,
+            false
+        )
+        .unwrap());
+
+        let reader_path = fifo_path.clone();
+        let reader = std::thread::spawn(move || std::fs::read_to_string(reader_path).unwrap());
+
+        while !write_waybar_fifo(
+            &waybar_output,
+            &fifo_path,
+            OutputTarget::Waybar,
+            false,
+            false,
+        )
+        .unwrap()
+        {
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+
+        assert_eq!(
+            reader.join().unwrap(),
+            format!("{}\n", serde_json::to_string(&waybar_output).unwrap())
+        );
+    }
+
+    #[test]
+    fn test_i3status_rust_block() {
+        let output = WaybarOutput {
+            text: "1 Renew the passport, Prio: H".to_string(),
+            class: Some("urgency-high active".to_string()),
+            ..sample_output()
+        };
+        let json = serialize_output(&output, OutputTarget::I3statusRust, false, false).unwrap();
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(&json).unwrap(),
+            serde_json::json!({
+                "text": "1 Renew the passport, Prio: H",
+                "short_text": "1 Renew the passpor…",
+                "state": "Critical",
+            })
+        );
+
+        let state = |class: Option<&str>| {
+            I3statusBlock::from(&WaybarOutput {
+                class: class.map(String::from),
+                ..sample_output()
+            })
+            .state
+        };
+        assert_eq!(state(Some("urgency-medium due-today")), "Warning");
+        assert_eq!(state(Some("urgency-low")), "Info");
+        assert_eq!(state(None), "Idle");
+    }
+
+    #[test]
+    fn test_plain_output() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("tasks.txt");
+        let output = WaybarOutput {
+            text: "<span foreground=\"#f38ba8\">1 Fix R&amp;D report</span>".to_string(),
+            tooltip: Some("1 Fix R&amp;D report\n2 Test".to_string()),
+            ..sample_output()
+        };
+        let mut sink = FileSink::new(path.clone(), true).with_target(OutputTarget::Plain, true);
+        sink.write(&output).unwrap();
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), "1 Fix R&D report\n");
+        assert!(sink.is_current(&output));
+    }
+
+    #[test]
+    fn test_plain_output_without_markup() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("tasks.txt");
+        let output = WaybarOutput {
+            text: "1 fix a <b> c, R&D <draft>".to_string(),
+            ..sample_output()
+        };
+        let mut sink = FileSink::new(path.clone(), false).with_target(OutputTarget::Plain, false);
+        sink.write(&output).unwrap();
+
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "1 fix a <b> c, R&D <draft>\n"
+        );
+    }
+
+    /// A writer whose reader went away, like stdout piped into `head`.
+    struct ClosedWriter;
+
+    impl Write for ClosedWriter {
+        fn write(&mut self, _buf: &[u8]) -> std::io::Result<usize> {
+            Err(std::io::ErrorKind::BrokenPipe.into())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_closed_writer_is_broken_pipe() {
+        let error = write_line(&mut ClosedWriter, "{}").unwrap_err();
+        assert!(error.is_broken_pipe(), "{:?}", error);
+
+        #[cfg(debug_assertions)]
+        assert!(debug::print_output(&mut ClosedWriter, &sample_output())
+            .unwrap_err()
+            .is_broken_pipe());
+
+        let mut out = Vec::new();
+        write_line(&mut out, "{}").unwrap();
+        assert_eq!(out, b"{}\n");
+    }
+
+    /// The keys waybar knows about, guarding against fields leaking into the json.
+    const WAYBAR_KEYS: [&str; 5] = ["text", "tooltip", "class", "percentage", "alt"];
+
+    fn assert_waybar_keys(output: &WaybarOutput) {
+        let serde_json::Value::Object(map) = serde_json::to_value(output).unwrap() else {
+            panic!("Expected a json object");
+        };
+        assert!(map.contains_key("text"));
+        for key in map.keys() {
+            assert!(
+                WAYBAR_KEYS.contains(&key.as_str()),
+                "Unexpected key {}",
+                key
+            );
+        }
+    }
+
+    #[test]
+    fn test_only_waybar_keys_are_serialized() {
+        assert_waybar_keys(&WaybarOutput {
+            text: "1 Task".to_string(),
+            tooltip: Some("1 Task".to_string()),
+            class: Some("urgency-high active".to_string()),
+            percentage: Some(40),
+            alt: Some("due-today".to_string()),
+        });
+
+        let tasks: Vec<Task> = serde_json::from_str(r#"[{"id":1,"description":"Task"}]"#).unwrap();
+        assert_waybar_keys(&generate_waybar_output(&tasks, &Config::default()));
+        assert_waybar_keys(&generate_waybar_output(&[], &Config::default()));
+    }
+
+    #[test]
+    fn test_empty_tooltip_is_omitted() {
+        assert_eq!(non_empty_tooltip(String::new()), None);
+        assert_eq!(
+            non_empty_tooltip("1 Task".to_string()).as_deref(),
+            Some("1 Task")
+        );
+
+        let waybar_output = WaybarOutput {
+            text: "1 Task".to_string(),
+            tooltip: non_empty_tooltip(String::new()),
+            class: None,
+            percentage: None,
+            alt: None,
+        };
+        assert_eq!(
+            serde_json::to_string(&waybar_output).unwrap(),
+            r#"{"text":"1 Task"}"#
+        );
+    }
+}
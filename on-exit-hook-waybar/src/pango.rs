@@ -0,0 +1,190 @@
+use log::warn;
+use std::collections::HashMap;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::Path;
+
+const DEFAULT_COLORS: [(&str, &str); 2] =
+    [("urgency-high", "#f38ba8"), ("urgency-medium", "#fab387")];
+
+/// Maps class names to the hex colors used for pango markup.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Palette {
+    colors: HashMap<String, String>,
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Palette {
+            colors: DEFAULT_COLORS
+                .iter()
+                .map(|(class, color)| (class.to_string(), color.to_string()))
+                .collect(),
+        }
+    }
+}
+
+impl Palette {
+    /// Loads the palette at `path`. Any problem with the file is logged and the
+    /// defaults are used instead, so a broken palette never stops the export.
+    pub fn load(path: &Path) -> Self {
+        match fs::read_to_string(path) {
+            Ok(content) => Self::parse(&content),
+            Err(e) if e.kind() == ErrorKind::NotFound => Palette::default(),
+            Err(e) => {
+                warn!("Failed to read palette {}: {}", path.display(), e);
+                Palette::default()
+            }
+        }
+    }
+
+    /// Parses `class = "#rrggbb"` entries on top of the defaults, skipping invalid colors.
+    pub fn parse(content: &str) -> Self {
+        let mut palette = Palette::default();
+
+        let entries: HashMap<String, String> = match toml::from_str(content) {
+            Ok(entries) => entries,
+            Err(e) => {
+                warn!("Failed to parse palette, using defaults: {}", e);
+                return palette;
+            }
+        };
+
+        for (class, color) in entries {
+            if is_valid_hex_color(&color) {
+                palette.colors.insert(class, color);
+            } else {
+                warn!("Ignoring invalid color {:?} for class {}", color, class);
+            }
+        }
+        palette
+    }
+
+    pub fn color_for(&self, class: &str) -> Option<&str> {
+        self.colors.get(class).map(String::as_str)
+    }
+}
+
+/// Accepts `#rgb`, `#rrggbb` and `#rrggbbaa`.
+pub fn is_valid_hex_color(color: &str) -> bool {
+    color.strip_prefix('#').is_some_and(|hex| {
+        matches!(hex.len(), 3 | 6 | 8) && hex.chars().all(|c| c.is_ascii_hexdigit())
+    })
+}
+
+/// Mixes two rgb colors, `t = 0` gives `from` and `t = 1` gives `to`.
+pub fn interpolate_color(from: (u8, u8, u8), to: (u8, u8, u8), t: f64) -> String {
+    let t = t.clamp(0.0, 1.0);
+    let mix = |a: u8, b: u8| (f64::from(a) + (f64::from(b) - f64::from(a)) * t).round() as u8;
+    format!(
+        "#{:02x}{:02x}{:02x}",
+        mix(from.0, to.0),
+        mix(from.1, to.1),
+        mix(from.2, to.2)
+    )
+}
+
+/// Escapes the characters pango would interpret as markup.
+pub fn escape_markup(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '\'' => escaped.push_str("&#39;"),
+            '"' => escaped.push_str("&quot;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Removes the tags from pango markup and turns the escapes of [`escape_markup`] back into
+/// their characters, for bars that show text as is.
+pub fn strip_markup(markup: &str) -> String {
+    let mut text = String::with_capacity(markup.len());
+    let mut rest = markup;
+    while let Some(start) = rest.find(['<', '&']) {
+        text.push_str(&rest[..start]);
+        rest = &rest[start..];
+        if rest.starts_with('<') {
+            match rest.find('>') {
+                Some(end) => rest = &rest[end + 1..],
+                None => break,
+            }
+            continue;
+        }
+        let entity = ["&amp;", "&lt;", "&gt;", "&#39;", "&quot;"]
+            .into_iter()
+            .zip(['&', '<', '>', '\'', '"'])
+            .find(|(entity, _)| rest.starts_with(entity));
+        match entity {
+            Some((entity, c)) => {
+                text.push(c);
+                rest = &rest[entity.len()..];
+            }
+            None => {
+                text.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    text.push_str(rest);
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_strip_markup() {
+        let markup = format!(
+            "<span foreground=\"#f38ba8\">1 {} <span foreground=\"#fff\">+home</span></span>",
+            escape_markup("Tom & Jerry's <show>")
+        );
+        assert_eq!(strip_markup(&markup), "1 Tom & Jerry's <show> +home");
+        assert_eq!(strip_markup("R&D"), "R&D");
+    }
+
+    #[test]
+    fn test_parse_palette() {
+        let palette = Palette::parse(
+            r##"
+            urgency-high = "#ff0000"
+            urgency-low = "#0f0"
+            "##,
+        );
+
+        assert_eq!(palette.color_for("urgency-high"), Some("#ff0000"));
+        assert_eq!(palette.color_for("urgency-low"), Some("#0f0"));
+        assert_eq!(palette.color_for("urgency-medium"), Some("#fab387"));
+        assert_eq!(palette.color_for("unknown"), None);
+    }
+
+    #[test]
+    fn test_invalid_palette_falls_back_to_defaults() {
+        let palette = Palette::parse(r#"urgency-high = "red""#);
+        assert_eq!(palette.color_for("urgency-high"), Some("#f38ba8"));
+
+        assert_eq!(Palette::parse("not toml ="), Palette::default());
+    }
+
+    #[test]
+    fn test_interpolate_color() {
+        let (red, green) = ((0xff, 0x00, 0x00), (0x00, 0xff, 0x00));
+        assert_eq!(interpolate_color(red, green, 0.0), "#ff0000");
+        assert_eq!(interpolate_color(red, green, 0.5), "#808000");
+        assert_eq!(interpolate_color(red, green, 1.0), "#00ff00");
+        assert_eq!(interpolate_color(red, green, 7.0), "#00ff00");
+    }
+
+    #[test]
+    fn test_escape_markup() {
+        assert_eq!(
+            escape_markup("Fix <b> & \"quotes\""),
+            "Fix &lt;b&gt; &amp; &quot;quotes&quot;"
+        );
+    }
+}
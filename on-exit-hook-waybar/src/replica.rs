@@ -0,0 +1,168 @@
+use crate::errors::TaskHookWaybarError;
+use crate::task::{TaskRecord, TaskSource};
+use log::info;
+use std::path::PathBuf;
+use taskchampion::storage::AccessMode;
+use taskchampion::{Replica, StorageConfig};
+
+/// The file taskwarrior 3 keeps its tasks in, inside `data.location`.
+const REPLICA_FILE_NAME: &str = "taskchampion.sqlite3";
+
+/// Reads the taskchampion replica of taskwarrior 3 instead of running `task export`.
+pub struct ReplicaSource {
+    taskdb_dir: PathBuf,
+    taskrc: Option<PathBuf>,
+}
+
+impl ReplicaSource {
+    /// The replica in `taskdb_dir`, for the user whose settings are in `taskrc`.
+    pub fn new(taskdb_dir: PathBuf, taskrc: Option<PathBuf>) -> Self {
+        ReplicaSource { taskdb_dir, taskrc }
+    }
+
+    /// The replica in `$TASKDATA`, else in `~/.task`, taskwarrior's default `data.location`,
+    /// with the taskrc in `$TASKRC`, else in `~/.taskrc`.
+    pub fn default_location() -> Option<Self> {
+        let home = dirs::home_dir();
+        let taskrc = std::env::var_os("TASKRC")
+            .map(PathBuf::from)
+            .or_else(|| home.as_ref().map(|home| home.join(".taskrc")));
+        std::env::var_os("TASKDATA")
+            .map(PathBuf::from)
+            .or_else(|| home.map(|home| home.join(".task")))
+            .map(|taskdb_dir| Self::new(taskdb_dir, taskrc))
+    }
+}
+
+/// Whether the taskrc sets one of the `urgency.*` settings, so only taskwarrior computes
+/// the urgency it exports.
+fn customizes_urgency(taskrc: &str) -> bool {
+    taskrc
+        .lines()
+        .any(|line| line.trim_start().starts_with("urgency."))
+}
+
+impl TaskSource for ReplicaSource {
+    /// `None` if there is no replica, or if the taskrc changes how urgency is computed. The
+    /// database is opened read-only and never created.
+    fn pending_records(&self) -> Result<Option<Vec<TaskRecord>>, TaskHookWaybarError> {
+        if !self.taskdb_dir.join(REPLICA_FILE_NAME).is_file() {
+            return Ok(None);
+        }
+        let taskrc = self
+            .taskrc
+            .as_ref()
+            .and_then(|path| std::fs::read_to_string(path).ok());
+        if taskrc.as_deref().is_some_and(customizes_urgency) {
+            info!("The taskrc sets urgency coefficients, exporting with task instead");
+            return Ok(None);
+        }
+        let storage = StorageConfig::OnDisk {
+            taskdb_dir: self.taskdb_dir.clone(),
+            create_if_missing: false,
+            access_mode: AccessMode::ReadOnly,
+        }
+        .into_storage()?;
+        Ok(Some(pending_records(&mut Replica::new(storage))?))
+    }
+}
+
+fn pending_records(replica: &mut Replica) -> Result<Vec<TaskRecord>, taskchampion::Error> {
+    let working_set = replica.working_set()?;
+    Ok(replica
+        .pending_task_data()?
+        .into_iter()
+        .map(|data| TaskRecord {
+            id: working_set
+                .by_uuid(data.get_uuid())
+                .and_then(|index| u32::try_from(index).ok())
+                .unwrap_or(0),
+            uuid: data.get_uuid().to_string(),
+            properties: data
+                .iter()
+                .map(|(key, value)| (key.clone(), value.clone()))
+                .collect(),
+        })
+        .collect())
+}
+
+/**************
+ * Unit tests *
+ **************/
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use taskchampion::{Operations, Status, Uuid};
+
+    #[test]
+    fn test_pending_records_from_replica() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = StorageConfig::OnDisk {
+            taskdb_dir: dir.path().to_path_buf(),
+            create_if_missing: true,
+            access_mode: AccessMode::ReadWrite,
+        }
+        .into_storage()
+        .unwrap();
+        let mut replica = Replica::new(storage);
+        let mut ops = Operations::new();
+        let uuid = Uuid::parse_str("5e0f9a3c-1d2b-4c5d-8e9f-0a1b2c3d4e5f").unwrap();
+        let mut task = replica.create_task(uuid, &mut ops).unwrap();
+        task.set_description("Pay rent".to_string(), &mut ops)
+            .unwrap();
+        task.set_status(Status::Pending, &mut ops).unwrap();
+        task.add_tag(&"home".try_into().unwrap(), &mut ops).unwrap();
+        let mut done = replica.create_task(Uuid::new_v4(), &mut ops).unwrap();
+        done.set_status(Status::Completed, &mut ops).unwrap();
+        replica.commit_operations(ops).unwrap();
+        replica.rebuild_working_set(true).unwrap();
+
+        drop(replica);
+
+        let records = ReplicaSource::new(dir.path().to_path_buf(), None)
+            .pending_records()
+            .unwrap()
+            .unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].id, 1);
+        assert_eq!(records[0].uuid, "5e0f9a3c-1d2b-4c5d-8e9f-0a1b2c3d4e5f");
+        assert_eq!(records[0].properties["description"], "Pay rent");
+        assert!(records[0].properties.contains_key("tag_home"));
+    }
+
+    #[test]
+    fn test_missing_replica_is_none() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = ReplicaSource::new(dir.path().to_path_buf(), None);
+        assert!(source.pending_records().unwrap().is_none());
+        assert!(!dir.path().join(REPLICA_FILE_NAME).exists());
+    }
+
+    #[test]
+    fn test_customized_urgency_is_none() {
+        let dir = tempfile::tempdir().unwrap();
+        StorageConfig::OnDisk {
+            taskdb_dir: dir.path().to_path_buf(),
+            create_if_missing: true,
+            access_mode: AccessMode::ReadWrite,
+        }
+        .into_storage()
+        .unwrap();
+        let taskrc = dir.path().join("taskrc");
+        std::fs::write(
+            &taskrc,
+            "include dark-256.theme\n# urgency.due.coefficient=1\n",
+        )
+        .unwrap();
+        let source = ReplicaSource::new(dir.path().to_path_buf(), Some(taskrc.clone()));
+        assert!(source.pending_records().unwrap().is_some());
+
+        std::fs::write(
+            &taskrc,
+            "news.version=3.1.0\n  urgency.due.coefficient=20\n",
+        )
+        .unwrap();
+        assert!(source.pending_records().unwrap().is_none());
+    }
+}
@@ -1,13 +1,122 @@
 use crate::errors::{InvalidRTSignalError, TaskHookWaybarError};
 use log::{info, warn};
 use procfs::process::{all_processes, Process};
+use regex::Regex;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
 
-fn calculate_signal_number(sig_offset: i32) -> Result<i32, InvalidRTSignalError> {
+/// Above this many matching processes, they are signalled from several threads, and no
+/// longer in ascending PID order.
+const PARALLEL_SIGNAL_THRESHOLD: usize = 16;
+const SIGNAL_THREADS: usize = 4;
+
+/// What the matchers need to know about a running process.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ProcessInfo {
+    pub pid: i32,
+    pub comm: String,
+    pub cmdline: Vec<String>,
+}
+
+/// Lists and signals processes, so the signalling logic can run against a fake in tests.
+///
+/// It is shared between the threads signalling many processes at once, so it has to be `Sync`.
+pub trait ProcessBackend: Sync {
+    fn processes(&self) -> Result<Vec<ProcessInfo>, TaskHookWaybarError>;
+    fn kill(&self, pid: i32, sig_num: i32) -> std::io::Result<()>;
+
+    /// Reads the process with `pid` again, `None` if it is gone.
+    fn process(&self, pid: i32) -> Option<ProcessInfo> {
+        self.processes()
+            .ok()?
+            .into_iter()
+            .find(|process| process.pid == pid)
+    }
+}
+
+/// The real processes, read from `/proc` and signalled with `kill(2)`.
+pub struct ProcfsBackend;
+
+impl ProcessBackend for ProcfsBackend {
+    fn processes(&self) -> Result<Vec<ProcessInfo>, TaskHookWaybarError> {
+        Ok(all_processes()
+            .map_err(TaskHookWaybarError::ProcUnavailable)?
+            .filter_map(Result::ok)
+            .filter_map(|process| process_info(&process))
+            .collect())
+    }
+
+    fn process(&self, pid: i32) -> Option<ProcessInfo> {
+        process_info(&Process::new(pid).ok()?)
+    }
+
+    fn kill(&self, pid: i32, sig_num: i32) -> std::io::Result<()> {
+        let result = unsafe { libc::kill(pid, sig_num) };
+        if result != 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        Ok(())
+    }
+}
+
+fn process_info(process: &Process) -> Option<ProcessInfo> {
+    Some(ProcessInfo {
+        pid: process.pid(),
+        comm: process.stat().ok()?.comm,
+        cmdline: process.cmdline().unwrap_or_default(),
+    })
+}
+
+/// Decides which processes get signalled.
+#[derive(Debug, Clone)]
+pub enum ProcessMatcher {
+    /// Any of these kernel process names, which are truncated to 15 characters.
+    Comm(Vec<String>),
+    /// Any of these executable file names in the command line, for wrapped binaries
+    /// whose `comm` differs (e.g. `.waybar-wrapped`).
+    Cmdline(Vec<String>),
+    /// A pattern matched against the whole command line, for wrapper scripts.
+    Regex(Regex),
+}
+
+impl ProcessMatcher {
+    fn matches(&self, process: &ProcessInfo) -> bool {
+        match self {
+            ProcessMatcher::Comm(names) => names.contains(&process.comm),
+            ProcessMatcher::Cmdline(names) => process
+                .cmdline
+                .first()
+                .and_then(|exe| Path::new(exe).file_name())
+                .is_some_and(|exe| names.iter().any(|name| exe == name.as_str())),
+            ProcessMatcher::Regex(pattern) => {
+                !process.cmdline.is_empty() && pattern.is_match(&process.cmdline.join(" "))
+            }
+        }
+    }
+
+    fn describe(&self) -> String {
+        match self {
+            ProcessMatcher::Comm(names) | ProcessMatcher::Cmdline(names) => {
+                format!("process named {}", names.join(" or "))
+            }
+            ProcessMatcher::Regex(pattern) => format!("process matching {}", pattern),
+        }
+    }
+}
+
+fn max_signal_offset() -> i32 {
+    libc::SIGRTMAX() - libc::SIGRTMIN()
+}
+
+pub(crate) fn calculate_signal_number(sig_offset: i32) -> Result<i32, InvalidRTSignalError> {
     if sig_offset < 1 {
         return Err(InvalidRTSignalError::BelowMinError {
             context: format!(
-                "Signal SIGRTMIN+{} is too low. Waybar only accepts signals >= SIGRTMIN+1",
-                sig_offset
+                "Signal SIGRTMIN+{} is too low. Waybar only accepts signals >= SIGRTMIN+1, \
+                 valid offsets are [1, {}]",
+                sig_offset,
+                max_signal_offset()
             ),
         });
     }
@@ -28,22 +137,50 @@ fn calculate_signal_number(sig_offset: i32) -> Result<i32, InvalidRTSignalError>
     Ok(sig_num)
 }
 
-fn get_processes_by_name(name: &str) -> Result<Vec<Process>, TaskHookWaybarError> {
-    Ok(all_processes()?
-        .filter_map(Result::ok)
-        .filter(|p| p.stat().is_ok_and(|s| s.comm == name))
+/// Describes which real signal an offset resolves to and what to configure in waybar.
+pub fn format_signal_mapping(sig_offset: i32) -> Result<String, InvalidRTSignalError> {
+    let sig_num = calculate_signal_number(sig_offset)?;
+    Ok(format!(
+        "SIGRTMIN+{} is signal {}, set \"signal\": {} in your waybar module",
+        sig_offset, sig_num, sig_offset
+    ))
+}
+
+fn get_matching_processes(
+    backend: &dyn ProcessBackend,
+    matcher: &ProcessMatcher,
+) -> Result<Vec<ProcessInfo>, TaskHookWaybarError> {
+    Ok(backend
+        .processes()?
+        .into_iter()
+        .filter(|p| matcher.matches(p))
         .collect())
 }
 
-fn send_signal(pid: i32, sig_num: i32) {
-    let result = unsafe { libc::kill(pid, sig_num) };
-    if result != 0 {
+/// Never signals PID 0 (the whole process group) or 1 (init). With `verify`, the process is
+/// read again right before and skipped if it no longer matches, in case its PID was reused.
+fn send_signal(
+    backend: &dyn ProcessBackend,
+    matcher: &ProcessMatcher,
+    pid: i32,
+    sig_num: i32,
+    verify: bool,
+) {
+    if pid <= 1 {
+        warn!("Refusing to send signal {} to PID {}", sig_num, pid);
+        return;
+    }
+    if verify && !backend.process(pid).is_some_and(|p| matcher.matches(&p)) {
         warn!(
-            "Failed to send signal {} to PID {}: {}",
-            sig_num,
+            "PID {} is no longer a {}, not sending signal {}",
             pid,
-            std::io::Error::last_os_error()
+            matcher.describe(),
+            sig_num
         );
+        return;
+    }
+    if let Err(e) = backend.kill(pid, sig_num) {
+        warn!("Failed to send signal {} to PID {}: {}", sig_num, pid, e);
     }
 }
 
@@ -51,14 +188,55 @@ pub fn send_offset_signal_to_process_by_name(
     process_name: &str,
     offset_from_sigrtmin: i32,
 ) -> Result<(), TaskHookWaybarError> {
-    send_signal_to_processes_by_name(process_name, calculate_signal_number(offset_from_sigrtmin)?)
+    send_offset_signal_to_matching_processes(
+        &ProcfsBackend,
+        &ProcessMatcher::Comm(vec![process_name.to_string()]),
+        offset_from_sigrtmin,
+        false,
+    )
+}
+
+pub fn send_offset_signal_to_matching_processes(
+    backend: &dyn ProcessBackend,
+    matcher: &ProcessMatcher,
+    offset_from_sigrtmin: i32,
+    verify: bool,
+) -> Result<(), TaskHookWaybarError> {
+    send_signal_to_matching_processes(
+        backend,
+        matcher,
+        calculate_signal_number(offset_from_sigrtmin)?,
+        verify,
+    )
 }
 
 pub fn send_signal_to_processes_by_name(
     process_name: &str,
     sig_num: i32,
 ) -> Result<(), TaskHookWaybarError> {
-    let processes = get_processes_by_name(process_name)?;
+    send_signal_to_matching_processes(
+        &ProcfsBackend,
+        &ProcessMatcher::Comm(vec![process_name.to_string()]),
+        sig_num,
+        false,
+    )
+}
+
+/// Signals the matching processes in ascending PID order, so the logs are the same from run
+/// to run. With more than `PARALLEL_SIGNAL_THRESHOLD` (16) processes they are split into
+/// ascending runs of PIDs signalled from `SIGNAL_THREADS` (4) threads at once, and the order
+/// across the runs, in the signals and in the logs, is unspecified.
+///
+/// `verify` checks each PID still is a matching process right before signalling it.
+pub fn send_signal_to_matching_processes(
+    backend: &dyn ProcessBackend,
+    matcher: &ProcessMatcher,
+    sig_num: i32,
+    verify: bool,
+) -> Result<(), TaskHookWaybarError> {
+    let mut processes = get_matching_processes(backend, matcher)?;
+    // Signal in a stable order, whatever order /proc lists them in.
+    processes.sort_unstable_by_key(|process| process.pid);
     let processes_len = processes.len();
 
     if processes_len == 0 {
@@ -76,16 +254,254 @@ pub fn send_signal_to_processes_by_name(
         );
     }
 
-    processes
-        .iter()
-        .map(|process| process.pid())
-        .for_each(|pid| {
+    let signal_all = |processes: &[ProcessInfo]| {
+        processes.iter().map(|process| process.pid).for_each(|pid| {
             info!("Sending to PID {}", pid);
-            send_signal(pid, sig_num);
+            send_signal(backend, matcher, pid, sig_num, verify);
+        })
+    };
+    if processes_len > PARALLEL_SIGNAL_THRESHOLD {
+        let chunk_len = processes_len.div_ceil(SIGNAL_THREADS);
+        std::thread::scope(|scope| {
+            for chunk in processes.chunks(chunk_len) {
+                scope.spawn(|| signal_all(chunk));
+            }
         });
+    } else {
+        signal_all(&processes);
+    }
+    Ok(())
+}
+
+/// Like [`send_offset_signal_to_matching_processes`], but when `strict` is false a missing
+/// process or an unreadable `/proc` is only logged as a warning instead of being an error.
+pub fn signal_waybar(
+    backend: &dyn ProcessBackend,
+    matcher: &ProcessMatcher,
+    offset_from_sigrtmin: i32,
+    strict: bool,
+    verify: bool,
+) -> Result<(), TaskHookWaybarError> {
+    match send_offset_signal_to_matching_processes(backend, matcher, offset_from_sigrtmin, verify) {
+        Err(TaskHookWaybarError::ProcessNotFound) if !strict => {
+            if detect_waybar_running() {
+                warn!(
+                    "No {} found, but waybar runtime files exist. \
+                     Is waybar running under a different name?",
+                    matcher.describe()
+                );
+            } else {
+                warn!("No {} found, skipping signal", matcher.describe());
+            }
+            Ok(())
+        }
+        Err(e @ TaskHookWaybarError::ProcUnavailable(_)) if !strict => {
+            warn!("{}, skipping signal", e);
+            Ok(())
+        }
+        result => result,
+    }
+}
+
+/// The `systemctl` arguments that send `sig_num` to the main process of `unit`. Only the
+/// main process, as the default action of a realtime signal would kill waybar's children.
+pub fn systemctl_kill_args(unit: &str, sig_num: i32) -> Vec<String> {
+    vec![
+        "--user".to_string(),
+        "kill".to_string(),
+        "--kill-whom=main".to_string(),
+        format!("--signal={}", sig_num),
+        unit.to_string(),
+    ]
+}
+
+/// Signals the systemd user service `unit`. When `strict` is false a failing `systemctl` is
+/// only logged as a warning.
+pub fn signal_systemd_unit(
+    unit: &str,
+    offset_from_sigrtmin: i32,
+    strict: bool,
+) -> Result<(), TaskHookWaybarError> {
+    let sig_num = calculate_signal_number(offset_from_sigrtmin)?;
+    info!("Sending signal {} to unit {}", sig_num, unit);
+    let output = Command::new("systemctl")
+        .args(systemctl_kill_args(unit, sig_num))
+        .output();
+    let message = match output {
+        Ok(output) if output.status.success() => return Ok(()),
+        Ok(output) => String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        Err(e) => e.to_string(),
+    };
+    let e = TaskHookWaybarError::Systemctl {
+        unit: unit.to_string(),
+        message,
+    };
+    if strict {
+        return Err(e);
+    }
+    warn!("{}, skipping signal", e);
     Ok(())
 }
 
+/// Whether no process matches, for `skip_if_no_waybar`. If the processes cannot be listed
+/// this is unknown, so the export goes ahead.
+pub fn no_waybar_running(backend: &dyn ProcessBackend, matcher: &ProcessMatcher) -> bool {
+    match get_matching_processes(backend, matcher) {
+        Ok(processes) => processes.is_empty(),
+        Err(e) => {
+            warn!("{}, exporting anyway", e);
+            false
+        }
+    }
+}
+
+/// Describes which PIDs [`send_offset_signal_to_matching_processes`] would signal, without
+/// sending anything.
+pub fn dry_run_signal(
+    backend: &dyn ProcessBackend,
+    matcher: &ProcessMatcher,
+    offset_from_sigrtmin: i32,
+) -> Result<String, TaskHookWaybarError> {
+    let sig_num = calculate_signal_number(offset_from_sigrtmin)?;
+    let mut pids: Vec<i32> = get_matching_processes(backend, matcher)?
+        .iter()
+        .map(|process| process.pid)
+        .collect();
+    if pids.is_empty() {
+        return Ok(format!(
+            "No {} found, would not send a signal",
+            matcher.describe()
+        ));
+    }
+    pids.sort_unstable();
+
+    let pids: Vec<String> = pids.iter().map(i32::to_string).collect();
+    Ok(format!(
+        "Would send SIGRTMIN+{} (signal {}) to PID(s) {}",
+        offset_from_sigrtmin,
+        sig_num,
+        pids.join(", ")
+    ))
+}
+
+/// Checks for waybar's runtime files under `$XDG_RUNTIME_DIR`.
+///
+/// This is a fallback for when process-name matching fails, e.g. because waybar
+/// is started through a wrapper with a different name.
+pub fn detect_waybar_running() -> bool {
+    std::env::var_os("XDG_RUNTIME_DIR")
+        .is_some_and(|dir| detect_waybar_in_runtime_dir(Path::new(&dir)))
+}
+
+fn detect_waybar_in_runtime_dir(runtime_dir: &Path) -> bool {
+    fs::read_dir(runtime_dir).is_ok_and(|entries| {
+        entries
+            .filter_map(Result::ok)
+            .any(|entry| is_waybar_runtime_file(&entry.file_name().to_string_lossy()))
+    })
+}
+
+/// Waybar's sockets, `waybar.sock` or `waybar-<n>.sock`. Other files that merely start with
+/// "waybar", like a lock file of some script, do not count.
+fn is_waybar_runtime_file(name: &str) -> bool {
+    let Some(rest) = name
+        .strip_prefix("waybar")
+        .and_then(|rest| rest.strip_suffix(".sock"))
+    else {
+        return false;
+    };
+    rest.is_empty()
+        || rest
+            .strip_prefix('-')
+            .is_some_and(|n| !n.is_empty() && n.bytes().all(|b| b.is_ascii_digit()))
+}
+
+/// What `--check` found, by process and via waybar's runtime files.
+#[derive(Debug, PartialEq)]
+pub struct WaybarCheck {
+    /// What the processes were matched by, like "process named waybar".
+    pub matcher: String,
+    /// The PIDs of the matching processes, ascending.
+    pub pids: Vec<i32>,
+    /// Whether waybar's runtime files are in `$XDG_RUNTIME_DIR`.
+    pub runtime_files: bool,
+}
+
+impl WaybarCheck {
+    pub fn found(&self) -> bool {
+        !self.pids.is_empty() || self.runtime_files
+    }
+}
+
+/// The runtime files only matter when no process matches.
+impl std::fmt::Display for WaybarCheck {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if !self.pids.is_empty() {
+            let pids: Vec<String> = self.pids.iter().map(i32::to_string).collect();
+            return writeln!(f, "Found {} PID(s): {}", self.matcher, pids.join(", "));
+        }
+        writeln!(f, "No {} found", self.matcher)?;
+        if self.runtime_files {
+            writeln!(f, "Found waybar runtime files in $XDG_RUNTIME_DIR")
+        } else {
+            writeln!(f, "No waybar runtime files found in $XDG_RUNTIME_DIR")
+        }
+    }
+}
+
+/// Looks for waybar, first by process and then via its runtime files, for `--check`.
+pub fn check_waybar(
+    backend: &dyn ProcessBackend,
+    matcher: &ProcessMatcher,
+) -> Result<WaybarCheck, TaskHookWaybarError> {
+    let runtime_dir = std::env::var_os("XDG_RUNTIME_DIR");
+    check_waybar_in(backend, matcher, runtime_dir.as_deref().map(Path::new))
+}
+
+fn check_waybar_in(
+    backend: &dyn ProcessBackend,
+    matcher: &ProcessMatcher,
+    runtime_dir: Option<&Path>,
+) -> Result<WaybarCheck, TaskHookWaybarError> {
+    let mut pids: Vec<i32> = get_matching_processes(backend, matcher)?
+        .iter()
+        .map(|process| process.pid)
+        .collect();
+    pids.sort_unstable();
+    Ok(WaybarCheck {
+        matcher: matcher.describe(),
+        pids,
+        runtime_files: runtime_dir.is_some_and(detect_waybar_in_runtime_dir),
+    })
+}
+
+/// One line per matching process with its PID, name and command line, for `--list-waybar`.
+/// Processes whose command line cannot be read (e.g. owned by another user) are still listed.
+pub fn list_waybar_processes(
+    backend: &dyn ProcessBackend,
+    matcher: &ProcessMatcher,
+) -> Result<String, TaskHookWaybarError> {
+    let mut processes = get_matching_processes(backend, matcher)?;
+    if processes.is_empty() {
+        return Ok(format!("No {} found\n", matcher.describe()));
+    }
+    processes.sort_unstable_by_key(|process| process.pid);
+
+    let mut listing = String::new();
+    for process in &processes {
+        let cmdline = if process.cmdline.is_empty() {
+            "(command line not readable)".to_string()
+        } else {
+            process.cmdline.join(" ")
+        };
+        listing.push_str(&format!(
+            "{:>7}  {:<15}  {}\n",
+            process.pid, process.comm, cmdline
+        ));
+    }
+    Ok(listing)
+}
+
 /**************
  * Unit tests *
  **************/
@@ -93,6 +509,66 @@ pub fn send_signal_to_processes_by_name(
 #[cfg(test)]
 mod tests {
     use super::*;
+    use procfs::process::Process;
+    use std::sync::Mutex;
+
+    /// A fixed process list that records the signals sent instead of sending them.
+    #[derive(Default)]
+    struct MockBackend {
+        processes: Vec<ProcessInfo>,
+        proc_unavailable: bool,
+        /// PIDs that belong to another process by the time they are read again.
+        reused_pids: Vec<i32>,
+        signalled: Mutex<Vec<(i32, i32)>>,
+    }
+
+    impl MockBackend {
+        fn with_processes(processes: &[(i32, &str)]) -> Self {
+            MockBackend {
+                processes: processes
+                    .iter()
+                    .map(|&(pid, comm)| ProcessInfo {
+                        pid,
+                        comm: comm.to_string(),
+                        cmdline: vec![format!("/usr/bin/{}", comm)],
+                    })
+                    .collect(),
+                ..MockBackend::default()
+            }
+        }
+    }
+
+    impl ProcessBackend for MockBackend {
+        fn processes(&self) -> Result<Vec<ProcessInfo>, TaskHookWaybarError> {
+            if self.proc_unavailable {
+                return Err(TaskHookWaybarError::ProcUnavailable(
+                    procfs::ProcError::PermissionDenied(Some("/proc".into())),
+                ));
+            }
+            Ok(self.processes.clone())
+        }
+
+        fn kill(&self, pid: i32, sig_num: i32) -> std::io::Result<()> {
+            self.signalled.lock().unwrap().push((pid, sig_num));
+            Ok(())
+        }
+
+        fn process(&self, pid: i32) -> Option<ProcessInfo> {
+            let process = self.processes.iter().find(|p| p.pid == pid)?;
+            if self.reused_pids.contains(&pid) {
+                return Some(ProcessInfo {
+                    pid,
+                    comm: "bash".to_string(),
+                    cmdline: vec!["/usr/bin/bash".to_string()],
+                });
+            }
+            Some(process.clone())
+        }
+    }
+
+    fn waybar() -> ProcessMatcher {
+        ProcessMatcher::Comm(vec!["waybar".to_string()])
+    }
 
     #[test]
     fn test_calculate_signal_number_valid() {
@@ -110,6 +586,17 @@ mod tests {
         ));
     }
 
+    #[test]
+    fn test_calculate_signal_number_below_min_suggests_range() {
+        let expected_range = format!("[1, {}]", libc::SIGRTMAX() - libc::SIGRTMIN());
+        match calculate_signal_number(0) {
+            Err(InvalidRTSignalError::BelowMinError { context }) => {
+                assert!(context.contains(&expected_range), "{}", context)
+            }
+            other => panic!("Expected BelowMinError, got {:?}", other),
+        }
+    }
+
     #[test]
     fn test_calculate_signal_number_negative_offset() {
         let result = calculate_signal_number(-500);
@@ -128,15 +615,65 @@ mod tests {
         ));
     }
 
+    #[test]
+    fn test_format_signal_mapping() {
+        assert_eq!(
+            format_signal_mapping(8).unwrap(),
+            format!(
+                "SIGRTMIN+8 is signal {}, set \"signal\": 8 in your waybar module",
+                libc::SIGRTMIN() + 8
+            )
+        );
+        assert!(format_signal_mapping(0).is_err());
+    }
+
+    #[test]
+    fn test_detect_waybar_in_empty_runtime_dir() {
+        let runtime_dir = tempfile::tempdir().unwrap();
+        assert!(!detect_waybar_in_runtime_dir(runtime_dir.path()));
+
+        fs::write(runtime_dir.path().join("wayland-1"), "").unwrap();
+        assert!(!detect_waybar_in_runtime_dir(runtime_dir.path()));
+    }
+
+    #[test]
+    fn test_detect_waybar_in_runtime_dir() {
+        let runtime_dir = tempfile::tempdir().unwrap();
+        fs::write(runtime_dir.path().join("waybar-1.sock"), "").unwrap();
+        assert!(detect_waybar_in_runtime_dir(runtime_dir.path()));
+    }
+
+    #[test]
+    fn test_detect_waybar_ignores_unrelated_runtime_files() {
+        let runtime_dir = tempfile::tempdir().unwrap();
+        fs::write(runtime_dir.path().join("waybar-foo.lock"), "").unwrap();
+        fs::write(runtime_dir.path().join("waybar-theme.sock"), "").unwrap();
+        fs::create_dir(runtime_dir.path().join("waybar-scripts")).unwrap();
+        assert!(!detect_waybar_in_runtime_dir(runtime_dir.path()));
+
+        fs::write(runtime_dir.path().join("waybar.sock"), "").unwrap();
+        assert!(detect_waybar_in_runtime_dir(runtime_dir.path()));
+    }
+
+    #[test]
+    fn test_detect_waybar_in_missing_runtime_dir() {
+        assert!(!detect_waybar_in_runtime_dir(Path::new(
+            "/nonexistent/runtime/dir"
+        )));
+    }
+
     #[test]
     fn test_retrieve_valid_processes() {
-        let procs = get_processes_by_name("cargo");
+        let procs = get_matching_processes(
+            &ProcfsBackend,
+            &ProcessMatcher::Comm(vec!["cargo".to_string()]),
+        );
         assert!(procs.is_ok());
 
         let procs = procs.unwrap();
         assert!(!procs.is_empty());
 
-        let pids: Vec<i32> = procs.iter().map(|p| p.pid()).collect();
+        let pids: Vec<i32> = procs.iter().map(|p| p.pid).collect();
 
         let procs_for_pids: Vec<Process> = all_processes()
             .unwrap()
@@ -148,4 +685,266 @@ mod tests {
             .iter()
             .all(|p| p.stat().unwrap().comm == "cargo"));
     }
+
+    fn matching_pids(matcher: &ProcessMatcher) -> Vec<i32> {
+        get_matching_processes(&ProcfsBackend, matcher)
+            .unwrap()
+            .iter()
+            .map(|p| p.pid)
+            .collect()
+    }
+
+    #[test]
+    fn test_signal_matching_processes() {
+        let backend = MockBackend::with_processes(&[(10, "waybar"), (11, "sway"), (12, "waybar")]);
+        signal_waybar(&backend, &waybar(), 8, true, false).unwrap();
+
+        let sig_num = libc::SIGRTMIN() + 8;
+        assert_eq!(
+            *backend.signalled.lock().unwrap(),
+            [(10, sig_num), (12, sig_num)]
+        );
+    }
+
+    #[test]
+    fn test_signal_several_process_names() {
+        let backend =
+            MockBackend::with_processes(&[(10, "waybar"), (11, "sway"), (12, "waybar-hypr")]);
+        let matcher = ProcessMatcher::Comm(vec!["waybar".to_string(), "waybar-hypr".to_string()]);
+        send_signal_to_matching_processes(&backend, &matcher, 40, false).unwrap();
+
+        assert_eq!(*backend.signalled.lock().unwrap(), [(10, 40), (12, 40)]);
+    }
+
+    #[test]
+    fn test_signal_in_ascending_pid_order() {
+        let backend =
+            MockBackend::with_processes(&[(300, "waybar"), (20, "waybar"), (1000, "waybar")]);
+        send_signal_to_matching_processes(&backend, &waybar(), 40, false).unwrap();
+
+        let pids: Vec<i32> = backend
+            .signalled
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|&(pid, _)| pid)
+            .collect();
+        assert_eq!(pids, [20, 300, 1000]);
+    }
+
+    #[test]
+    fn test_signal_in_ascending_pid_order_up_to_threshold() {
+        let processes: Vec<(i32, &str)> = (0..PARALLEL_SIGNAL_THRESHOLD as i32)
+            .rev()
+            .map(|i| (100 + i, "waybar"))
+            .collect();
+        let backend = MockBackend::with_processes(&processes);
+        send_signal_to_matching_processes(&backend, &waybar(), 40, false).unwrap();
+
+        let pids: Vec<i32> = backend
+            .signalled
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|&(pid, _)| pid)
+            .collect();
+        let expected: Vec<i32> = (100..100 + PARALLEL_SIGNAL_THRESHOLD as i32).collect();
+        assert_eq!(pids, expected);
+    }
+
+    #[test]
+    fn test_signal_many_processes_from_threads() {
+        let processes: Vec<(i32, &str)> = (100..200).map(|pid| (pid, "waybar")).collect();
+        let backend = MockBackend::with_processes(&processes);
+        send_signal_to_matching_processes(&backend, &waybar(), 40, true).unwrap();
+
+        // The threads signal in no particular order among each other.
+        let mut signalled = backend.signalled.lock().unwrap().clone();
+        signalled.sort_unstable();
+        let expected: Vec<(i32, i32)> = (100..200).map(|pid| (pid, 40)).collect();
+        assert_eq!(signalled, expected);
+    }
+
+    #[test]
+    fn test_never_signal_pid_0_or_1() {
+        let backend = MockBackend::with_processes(&[(0, "waybar"), (1, "waybar"), (42, "waybar")]);
+        send_signal_to_matching_processes(&backend, &waybar(), 40, false).unwrap();
+
+        assert_eq!(*backend.signalled.lock().unwrap(), [(42, 40)]);
+    }
+
+    #[test]
+    fn test_verify_before_signal() {
+        let backend = MockBackend {
+            reused_pids: vec![10],
+            ..MockBackend::with_processes(&[(10, "waybar"), (12, "waybar")])
+        };
+        send_signal_to_matching_processes(&backend, &waybar(), 40, true).unwrap();
+        assert_eq!(*backend.signalled.lock().unwrap(), [(12, 40)]);
+
+        backend.signalled.lock().unwrap().clear();
+        send_signal_to_matching_processes(&backend, &waybar(), 40, false).unwrap();
+        assert_eq!(*backend.signalled.lock().unwrap(), [(10, 40), (12, 40)]);
+    }
+
+    #[test]
+    fn test_no_waybar_running() {
+        let backend = MockBackend::with_processes(&[(11, "sway")]);
+        assert!(no_waybar_running(&backend, &waybar()));
+
+        let backend = MockBackend::with_processes(&[(10, "waybar"), (11, "sway")]);
+        assert!(!no_waybar_running(&backend, &waybar()));
+
+        let backend = MockBackend {
+            proc_unavailable: true,
+            ..MockBackend::default()
+        };
+        assert!(!no_waybar_running(&backend, &waybar()));
+    }
+
+    #[test]
+    fn test_dry_run_signal() {
+        let backend = MockBackend::with_processes(&[(12, "waybar"), (11, "sway"), (10, "waybar")]);
+        assert_eq!(
+            dry_run_signal(&backend, &waybar(), 8).unwrap(),
+            format!(
+                "Would send SIGRTMIN+8 (signal {}) to PID(s) 10, 12",
+                libc::SIGRTMIN() + 8
+            )
+        );
+        assert!(backend.signalled.lock().unwrap().is_empty());
+
+        let backend = MockBackend::with_processes(&[(11, "sway")]);
+        assert_eq!(
+            dry_run_signal(&backend, &waybar(), 8).unwrap(),
+            "No process named waybar found, would not send a signal"
+        );
+    }
+
+    #[test]
+    fn test_list_waybar_processes() {
+        let mut backend =
+            MockBackend::with_processes(&[(4321, "waybar"), (11, "sway"), (987, "waybar")]);
+        backend.processes[0].cmdline = vec![
+            "/usr/bin/waybar".to_string(),
+            "-c".to_string(),
+            "bar.json".to_string(),
+        ];
+        backend.processes[2].cmdline.clear();
+        assert_eq!(
+            list_waybar_processes(&backend, &waybar()).unwrap(),
+            "    987  waybar           (command line not readable)\n\
+             \x20  4321  waybar           /usr/bin/waybar -c bar.json\n"
+        );
+
+        let backend = MockBackend::with_processes(&[(11, "sway")]);
+        assert_eq!(
+            list_waybar_processes(&backend, &waybar()).unwrap(),
+            "No process named waybar found\n"
+        );
+    }
+
+    #[test]
+    fn test_check_waybar() {
+        let runtime_dir = tempfile::tempdir().unwrap();
+        let backend =
+            MockBackend::with_processes(&[(4321, "waybar"), (11, "sway"), (987, "waybar")]);
+        let check = check_waybar_in(&backend, &waybar(), Some(runtime_dir.path())).unwrap();
+        assert!(check.found());
+        assert_eq!(check.pids, vec![987, 4321]);
+        assert_eq!(
+            check.to_string(),
+            "Found process named waybar PID(s): 987, 4321\n"
+        );
+
+        let backend = MockBackend::with_processes(&[(11, "sway")]);
+        let check = check_waybar_in(&backend, &waybar(), Some(runtime_dir.path())).unwrap();
+        assert!(!check.found());
+        assert_eq!(
+            check.to_string(),
+            "No process named waybar found\n\
+             No waybar runtime files found in $XDG_RUNTIME_DIR\n"
+        );
+
+        fs::write(runtime_dir.path().join("waybar-1.sock"), "").unwrap();
+        let check = check_waybar_in(&backend, &waybar(), Some(runtime_dir.path())).unwrap();
+        assert!(check.found());
+        assert!(check
+            .to_string()
+            .ends_with("Found waybar runtime files in $XDG_RUNTIME_DIR\n"));
+        assert!(!check_waybar_in(&backend, &waybar(), None).unwrap().found());
+    }
+
+    #[test]
+    fn test_systemctl_kill_args() {
+        assert_eq!(
+            systemctl_kill_args("waybar.service", 42),
+            [
+                "--user",
+                "kill",
+                "--kill-whom=main",
+                "--signal=42",
+                "waybar.service"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_unavailable_proc() {
+        let backend = MockBackend {
+            proc_unavailable: true,
+            ..MockBackend::default()
+        };
+
+        let error = signal_waybar(&backend, &waybar(), 8, true, false).unwrap_err();
+        assert!(matches!(error, TaskHookWaybarError::ProcUnavailable(_)));
+        assert!(error.to_string().contains("/proc"), "{}", error);
+
+        assert!(signal_waybar(&backend, &waybar(), 8, false, false).is_ok());
+        assert!(backend.signalled.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_match_own_process_by_comm() {
+        let myself = Process::myself().unwrap();
+        let comm = myself.stat().unwrap().comm;
+
+        assert!(matching_pids(&ProcessMatcher::Comm(vec![comm])).contains(&myself.pid()));
+        assert!(
+            !matching_pids(&ProcessMatcher::Comm(vec!["no-such-process".to_string()]))
+                .contains(&myself.pid())
+        );
+    }
+
+    #[test]
+    fn test_match_own_process_by_cmdline() {
+        let myself = Process::myself().unwrap();
+        let exe = myself.cmdline().unwrap().remove(0);
+        let exe_name = Path::new(&exe).file_name().unwrap().to_string_lossy();
+
+        let pids = matching_pids(&ProcessMatcher::Cmdline(vec![exe_name.to_string()]));
+        assert!(pids.contains(&myself.pid()));
+
+        // Only the executable counts, not its directory.
+        let dir_name = Path::new(&exe)
+            .parent()
+            .and_then(Path::file_name)
+            .unwrap()
+            .to_string_lossy();
+        let pids = matching_pids(&ProcessMatcher::Cmdline(vec![dir_name.to_string()]));
+        assert!(!pids.contains(&myself.pid()));
+    }
+
+    #[test]
+    fn test_match_own_process_by_regex() {
+        let myself = Process::myself().unwrap();
+        let exe = myself.cmdline().unwrap().remove(0);
+        let exe_name = Path::new(&exe).file_name().unwrap().to_string_lossy();
+
+        let pattern = Regex::new(&format!("{}( |$)", regex::escape(&exe_name))).unwrap();
+        assert!(matching_pids(&ProcessMatcher::Regex(pattern)).contains(&myself.pid()));
+
+        let pattern = Regex::new("^no-such-wrapper ").unwrap();
+        assert!(!matching_pids(&ProcessMatcher::Regex(pattern)).contains(&myself.pid()));
+    }
 }
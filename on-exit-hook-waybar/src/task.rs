@@ -1,189 +1,1691 @@
-use crate::errors::TaskHookWaybarError;
-use chrono::{DateTime, Local};
-use log::info;
+use crate::config::{
+    AltSource, Config, DatePreference, Field, IdDisplay, OverdueSource, PercentageScale,
+    PriorityStyle, RelativeGranularity, SortKey, TextMode, TextTieMode, TooltipFormat, TooltipSort,
+    DEFAULT_DUE_FORMAT,
+};
+use crate::errors::{InvalidDateError, TaskHookWaybarError};
+use crate::output::{non_empty_tooltip, FileSink, OutputSink, WaybarOutput};
+use crate::pango::{escape_markup, interpolate_color};
+use crate::template::Template;
+use crate::urgency::record_urgencies;
+use crate::utils::{prefix_within_bytes, truncate_chars};
+use chrono::{DateTime, Local, Locale, NaiveDateTime, TimeZone};
 use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::cmp::{Ordering, Reverse};
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::path::Path;
 use std::process::Command;
-use std::{
-    fs::OpenOptions,
-    io::{BufWriter, Write},
-    path::PathBuf,
-};
 
-#[derive(Serialize, Debug, PartialEq)]
-pub struct WaybarOutput {
-    text: String,
-    tooltip: String,
-}
+/// Rough length of a rendered task line, used to pre-size the tooltip buffer.
+const ESTIMATED_LINE_LEN: usize = 80;
+const URGENCY_BAR_WIDTH: usize = 6;
+const NEXT_HINT_MAX_LEN: usize = 20;
+const WAITING_FILTER: &str = "status:waiting";
+/// The default `filter`, the only one a [`TaskSource`] can answer without taskwarrior.
+const PENDING_FILTER: &str = "status:pending";
+const GRADIENT_OVERDUE: (u8, u8, u8) = (0xf3, 0x8b, 0xa8);
+const GRADIENT_FAR: (u8, u8, u8) = (0xa6, 0xe3, 0xa1);
+const WAITING_HEADING: &str = "── Waiting ──";
+const OVERDUE_TAG: &str = "OVERDUE";
+const SHORT_UUID_LEN: usize = 8;
 
-#[derive(Deserialize, Debug, PartialEq)]
-struct Task {
+#[derive(Deserialize, Debug, Default, Clone, PartialEq)]
+pub struct Task {
     id: u32,
+    uuid: Option<String>,
+    status: Option<String>,
     description: Option<String>,
     priority: Option<String>,
     due: Option<String>,
+    scheduled: Option<String>,
     urgency: Option<f64>,
+    recur: Option<String>,
+    start: Option<String>,
+    #[serde(default)]
+    tags: Vec<String>,
+    entry: Option<String>,
+    modified: Option<String>,
+    #[serde(default)]
+    annotations: Vec<Annotation>,
+}
+
+#[derive(Deserialize, Debug, Default, Clone, PartialEq)]
+struct Annotation {
+    entry: Option<String>,
+    description: String,
+}
+
+/// Fields left out of the bar text, in this order, until it fits `compact_when_over`.
+const COMPACT_DROP_ORDER: [Field; 3] = [Field::Urgency, Field::Due, Field::Priority];
+
+/// Where a rendered task line ends up.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Line {
+    Text,
+    Tooltip,
 }
 
 impl Task {
-    fn construct_task_output(&self) -> String {
-        let parts: Vec<_> = [
-            self.description.as_deref().map(String::from),
-            self.priority.as_ref().map(|p| format!("Prio: {}", p)),
-            self.due.as_ref().and_then(|d| {
-                parse_due_date(d)
-                    .ok()
-                    .map(|datetime| format!("Due: {}", datetime.format("%a, %y-%m-%d %H:%M")))
+    fn construct_task_output(&self, config: &Config, line: Line, now: DateTime<Local>) -> String {
+        let mut output = String::new();
+        self.write_task_output(&mut output, config, line, now);
+        output
+    }
+
+    /// Appends the rendered task to `output` instead of allocating a new line.
+    fn write_task_output(
+        &self,
+        output: &mut String,
+        config: &Config,
+        line: Line,
+        now: DateTime<Local>,
+    ) {
+        self.write_task_fields(output, config, line, &[], now);
+    }
+
+    /// Renders the bar text from `text_template` if there is one. Otherwise from the fields,
+    /// dropping those in [`COMPACT_DROP_ORDER`] while it is longer than `compact_when_over`
+    /// characters.
+    fn render_text(&self, config: &Config, now: DateTime<Local>) -> String {
+        if let Some(template) = &config.text_template {
+            return self.render_template(template, config, now);
+        }
+        let mut text = self.construct_task_output(config, Line::Text, now);
+        let Some(max_len) = config.compact_when_over else {
+            return text;
+        };
+        for dropped in 1..=COMPACT_DROP_ORDER.len() {
+            if text.chars().count() <= max_len {
+                break;
+            }
+            text.clear();
+            self.write_task_fields(
+                &mut text,
+                config,
+                Line::Text,
+                &COMPACT_DROP_ORDER[..dropped],
+                now,
+            );
+        }
+        text
+    }
+
+    /// Renders the bar text from `text_template` instead of `field_order`, colored like the
+    /// fields would be.
+    fn render_template(
+        &self,
+        template: &Template,
+        config: &Config,
+        now: DateTime<Local>,
+    ) -> String {
+        let value = |name: &str| {
+            let field = match name {
+                "id" => return self.shown_id(config),
+                "description" => Field::Description,
+                "tags" => Field::Tags,
+                "priority" => Field::Priority,
+                "due" => Field::Due,
+                "urgency" => Field::Urgency,
+                _ => return None,
+            };
+            self.render_field_value(field, config, Line::Text, now)
+        };
+        let holds = |condition: &str| match condition {
+            "overdue" => self.is_overdue(config.overdue_source, now),
+            "due" => self.due.is_some(),
+            "active" => self.start.is_some(),
+            "tagged" => !self.tags.is_empty(),
+            _ => false,
+        };
+        let text = template.render(&value, &holds);
+        match config
+            .pango_markup
+            .then(|| self.line_color(config, Line::Text, now))
+            .flatten()
+        {
+            Some(color) => format!("<span foreground=\"{}\">{}</span>", color, text),
+            None => text,
+        }
+    }
+
+    /// The identifier in front of the task as chosen by `id_display`, the id for tasks
+    /// without a uuid.
+    fn shown_id(&self, config: &Config) -> Option<String> {
+        match (config.id_display, self.uuid.as_deref()) {
+            (IdDisplay::None, _) => None,
+            (IdDisplay::UuidShort, Some(uuid)) => {
+                Some(uuid.get(..SHORT_UUID_LEN).unwrap_or(uuid).to_string())
+            }
+            (IdDisplay::Id | IdDisplay::UuidShort, _) => Some(self.id.to_string()),
+        }
+    }
+
+    fn write_task_fields(
+        &self,
+        output: &mut String,
+        config: &Config,
+        line: Line,
+        dropped: &[Field],
+        now: DateTime<Local>,
+    ) {
+        let parts = config
+            .field_order
+            .iter()
+            .filter(|field| !dropped.contains(field))
+            .map(|field| self.render_field(*field, config, line, now));
+
+        let color = config
+            .pango_markup
+            .then(|| self.line_color(config, line, now))
+            .flatten();
+        if let Some(color) = &color {
+            let _ = write!(output, "<span foreground=\"{}\">", color);
+        }
+
+        if let Some(prefix) = self
+            .status_prefix()
+            .filter(|_| config.status_prefix && line == Line::Tooltip)
+        {
+            output.push_str(prefix);
+        }
+        if let Some(id) = self.shown_id(config) {
+            let _ = write!(output, "{} ", id);
+        }
+        for (i, part) in parts.flatten().enumerate() {
+            if i > 0 {
+                output.push_str(", ");
+            }
+            output.push_str(&part);
+        }
+        if config.annotation_count_marker && !self.annotations.is_empty() {
+            let _ = write!(output, " 📎{}", self.annotations.len());
+        }
+
+        if color.is_some() {
+            output.push_str("</span>");
+        }
+    }
+
+    /// Marks tasks that are not pending, for filters that include other statuses.
+    fn status_prefix(&self) -> Option<&'static str> {
+        match self.status.as_deref()? {
+            "waiting" => Some("⏸ "),
+            "completed" => Some("✓ "),
+            "deleted" => Some("✗ "),
+            "recurring" => Some("↻ "),
+            _ => None,
+        }
+    }
+
+    /// The pango color of the line: by due date for tooltip lines with `due_gradient`,
+    /// else by taskwarrior's color rules with `taskwarrior_colors`, else by urgency class.
+    fn line_color(&self, config: &Config, line: Line, now: DateTime<Local>) -> Option<String> {
+        let due = self.due.as_deref().and_then(|d| parse_due_date(d).ok());
+        if config.due_gradient && line == Line::Tooltip {
+            if self.is_overdue(config.overdue_source, now) {
+                return Some(interpolate_color(GRADIENT_OVERDUE, GRADIENT_FAR, 0.0));
+            }
+            if let Some(due) = due {
+                return Some(due_gradient_color(due - now, config.due_gradient_days));
+            }
+        }
+        let taskwarrior_color = config.task_colors.color_for(
+            &self.tags,
+            self.priority.as_deref(),
+            self.start.is_some(),
+        );
+        if let Some(color) = taskwarrior_color.filter(|_| config.taskwarrior_colors) {
+            return Some(color.to_string());
+        }
+        let class = urgency_class(self.urgency.unwrap_or(0.0), config);
+        config.palette.color_for(class).map(String::from)
+    }
+
+    fn render_field(
+        &self,
+        field: Field,
+        config: &Config,
+        line: Line,
+        now: DateTime<Local>,
+    ) -> Option<String> {
+        let Some(value) = self.render_field_value(field, config, line, now) else {
+            let marks_no_due =
+                field == Field::Due && line == Line::Tooltip && !config.no_due_marker.is_empty();
+            return marks_no_due.then(|| config.no_due_marker.clone());
+        };
+        // "Due today 14:30" reads better without the colon.
+        let relative = field == Field::Due && self.relative_due_label(config, now).is_some();
+        let label = match field {
+            Field::Due => self.shown_date(config).map(|(label, _)| label),
+            Field::Priority if config.priority_style == PriorityStyle::Bang => None,
+            field => field_label(field, config),
+        };
+        Some(match label.filter(|label| !label.is_empty()) {
+            Some(label) if relative => format!("{} {}", label, value),
+            Some(label) => format!("{}: {}", label, value),
+            None => value,
+        })
+    }
+
+    fn render_field_value(
+        &self,
+        field: Field,
+        config: &Config,
+        line: Line,
+        now: DateTime<Local>,
+    ) -> Option<String> {
+        match field {
+            Field::Description => self.render_description(config, line),
+            Field::Tags => self.render_tags(config),
+            Field::Priority => self
+                .priority
+                .as_deref()
+                .map(|priority| format_priority(priority, config.priority_style)),
+            Field::Due => self.relative_due_label(config, now).or_else(|| {
+                self.shown_date(config)
+                    .map(|(_, datetime)| format_due(datetime, config))
             }),
-            self.urgency.map(|u| format!("Urgency: {:.2}", u)),
-        ]
+            Field::Urgency => self.urgency.map(|u| format_urgency(u, config)),
+        }
+    }
+
+    /// The task as a json object of its rendered fields for `tooltip_format = "json"`.
+    fn to_tooltip_json(&self, config: &Config, now: DateTime<Local>) -> serde_json::Value {
+        let mut entry = serde_json::Map::new();
+        entry.insert("id".to_string(), self.id.into());
+        for field in &config.field_order {
+            if let Some(value) = self.render_field_value(*field, config, Line::Tooltip, now) {
+                entry.insert(field_key(*field).to_string(), value.into());
+            }
+        }
+        entry.into()
+    }
+
+    /// The date shown in the due field and its label, due or scheduled as chosen by
+    /// `date_preference`.
+    fn shown_date<'a>(&self, config: &'a Config) -> Option<(&'a str, DateTime<Local>)> {
+        let parse = |date: &Option<String>| date.as_deref().and_then(|d| parse_due_date(d).ok());
+        let due = parse(&self.due).map(|due| (config.due_label.as_str(), due));
+        let scheduled =
+            parse(&self.scheduled).map(|scheduled| (config.scheduled_label.as_str(), scheduled));
+        match config.date_preference {
+            DatePreference::Due => due,
+            DatePreference::Scheduled => scheduled.or(due),
+            DatePreference::Earliest => match (due, scheduled) {
+                (Some(due), Some(scheduled)) => {
+                    Some(if scheduled.1 < due.1 { scheduled } else { due })
+                }
+                (due, scheduled) => due.or(scheduled),
+            },
+        }
+    }
+
+    /// How long a started task has been active, like `25m`. `None` if it is not started or
+    /// `start` can't be parsed.
+    fn active_elapsed(&self, now: DateTime<Local>) -> Option<String> {
+        let start = parse_due_date(self.start.as_deref()?).ok()?;
+        let seconds = (now - start).num_seconds().max(0);
+        if seconds < 60 {
+            return Some("0m".to_string());
+        }
+        Some(humanize_seconds(seconds, RelativeGranularity::Fine))
+    }
+
+    /// The due field shown relative to `now`, by `relative_due` or else by `smart_due`.
+    fn relative_due_label(&self, config: &Config, now: DateTime<Local>) -> Option<String> {
+        if config.relative_due {
+            let (_, due) = self.shown_date(config)?;
+            return Some(humanize_delta(due - now, config.relative_granularity));
+        }
+        self.smart_due_label(config, now)
+    }
+
+    /// With `smart_due`, "today 14:30", "tomorrow 14:30" or the weekday for tasks due
+    /// within the coming week. `None` for overdue tasks and those further out.
+    fn smart_due_label(&self, config: &Config, now: DateTime<Local>) -> Option<String> {
+        if !config.smart_due {
+            return None;
+        }
+        let (_, due) = self.shown_date(config)?;
+        if due < now {
+            return None;
+        }
+        let days = (due.date_naive() - now.date_naive()).num_days();
+        let locale = config.date_locale().unwrap_or(Locale::POSIX);
+        let time = due.format("%H:%M");
+        match days {
+            0 => Some(format!("today {}", time)),
+            1 => Some(format!("tomorrow {}", time)),
+            2..=6 => Some(format!("{} {}", due.format_localized("%A", locale), time)),
+            _ => None,
+        }
+    }
+
+    /// Whether the task is due on the same local date as `now`.
+    fn is_due_today(&self, now: DateTime<Local>) -> bool {
+        self.due
+            .as_deref()
+            .and_then(|d| parse_due_date(d).ok())
+            .is_some_and(|due| due.date_naive() == now.date_naive())
+    }
+
+    /// Whether the task is overdue, see [`OverdueSource`]. With `OverdueSource::Tag`, the
+    /// `+OVERDUE` tag wins over a due date that is still ahead.
+    fn is_overdue(&self, source: OverdueSource, now: DateTime<Local>) -> bool {
+        if source == OverdueSource::Tag && self.tags.iter().any(|tag| tag == OVERDUE_TAG) {
+            return true;
+        }
+        self.due
+            .as_deref()
+            .and_then(|d| parse_due_date(d).ok())
+            .is_some_and(|due| due < now)
+    }
+
+    /// Whether the task is overdue or due within `days` of `now`.
+    fn is_due_within(&self, days: f64, config: &Config, now: DateTime<Local>) -> bool {
+        if self.is_overdue(config.overdue_source, now) {
+            return true;
+        }
+        self.due
+            .as_deref()
+            .and_then(|d| parse_due_date(d).ok())
+            .is_some_and(|due| ((due - now).num_seconds() as f64) <= days * 86_400.0)
+    }
+
+    /// Renders `+tag` tokens, colored by `tag_colors` when pango markup is enabled.
+    fn render_tags(&self, config: &Config) -> Option<String> {
+        if !config.show_tags || self.tags.is_empty() {
+            return None;
+        }
+
+        let tokens: Vec<String> = self
+            .tags
+            .iter()
+            .map(|tag| {
+                if !config.pango_markup {
+                    return format!("+{}", tag);
+                }
+                let token = format!("+{}", escape_markup(tag));
+                match config.tag_color(tag) {
+                    Some(color) => format!("<span foreground=\"{}\">{}</span>", color, token),
+                    None => token,
+                }
+            })
+            .collect();
+        Some(tokens.join(" "))
+    }
+
+    fn render_description(&self, config: &Config, line: Line) -> Option<String> {
+        let description = match self.description.as_deref() {
+            Some(d) => Some(tidy_whitespace(
+                sanitize_newlines(d, &config.newline_placeholder),
+                config,
+            )),
+            None if !config.missing_description_placeholder.is_empty() => {
+                Some(config.missing_description_placeholder.clone())
+            }
+            None => None,
+        };
+
+        let max_len = match line {
+            Line::Text => config.max_text_len,
+            Line::Tooltip => config.max_tooltip_desc_len,
+        };
+        let description = match max_len {
+            Some(max_len) => description.map(|d| truncate_chars(&d, max_len)),
+            None => description,
+        };
+        let description = if config.escape_placeholders {
+            description.map(|d| escape_placeholders(&d))
+        } else {
+            description
+        };
+
+        let description = if config.pango_markup {
+            description.map(|d| escape_markup(&d))
+        } else {
+            description
+        };
+
+        let show_recurrence = self.recur.is_some()
+            && !config.recurrence_marker.is_empty()
+            && (line == Line::Tooltip || config.recurrence_marker_in_text);
+        if !show_recurrence {
+            return description;
+        }
+
+        Some(match description {
+            Some(description) => format!("{} {}", description, config.recurrence_marker),
+            None => config.recurrence_marker.clone(),
+        })
+    }
+}
+
+/// Formats with `due_format`, falling back to the C locale if `locale` is unknown and
+/// to the default format if `due_format` is invalid.
+fn format_due(due: DateTime<Local>, config: &Config) -> String {
+    let locale = config.date_locale().unwrap_or(Locale::POSIX);
+    let mut formatted = String::new();
+    if write!(
+        formatted,
+        "{}",
+        due.format_localized(&config.due_format, locale)
+    )
+    .is_err()
+    {
+        formatted.clear();
+        let _ = write!(
+            formatted,
+            "{}",
+            due.format_localized(DEFAULT_DUE_FORMAT, locale)
+        );
+    }
+    formatted
+}
+
+/// Fades from green for tasks due in `horizon_days` or later to red once overdue.
+fn due_gradient_color(until_due: chrono::TimeDelta, horizon_days: f64) -> String {
+    let days = until_due.num_seconds() as f64 / 86_400.0;
+    let t = if horizon_days > 0.0 {
+        (days / horizon_days).clamp(0.0, 1.0)
+    } else if days > 0.0 {
+        1.0
+    } else {
+        0.0
+    };
+    interpolate_color(GRADIENT_OVERDUE, GRADIENT_FAR, t)
+}
+
+/// Like `in 1h 59m` or `3d ago`, `now` within a minute. Fine shows the two largest units,
+/// coarse rounds to the largest.
+fn humanize_delta(delta: chrono::TimeDelta, granularity: RelativeGranularity) -> String {
+    const MINUTE: i64 = 60;
+
+    let seconds = delta.num_seconds().abs();
+    if seconds < MINUTE {
+        return "now".to_string();
+    }
+    let amount = humanize_seconds(seconds, granularity);
+    if delta < chrono::TimeDelta::zero() {
+        format!("{} ago", amount)
+    } else {
+        format!("in {}", amount)
+    }
+}
+
+/// A duration of at least a minute like `1h 59m`, or `2h` when coarse.
+fn humanize_seconds(seconds: i64, granularity: RelativeGranularity) -> String {
+    const MINUTE: i64 = 60;
+    const HOUR: i64 = 60 * MINUTE;
+    const DAY: i64 = 24 * HOUR;
+
+    let round = |unit: i64| (seconds + unit / 2) / unit;
+    match granularity {
+        RelativeGranularity::Coarse if round(MINUTE) < 60 => format!("{}m", round(MINUTE)),
+        RelativeGranularity::Coarse if round(HOUR) < 24 => format!("{}h", round(HOUR)),
+        RelativeGranularity::Coarse => format!("{}d", round(DAY)),
+        RelativeGranularity::Fine => {
+            let units = [
+                (seconds / DAY, "d"),
+                (seconds % DAY / HOUR, "h"),
+                (seconds % HOUR / MINUTE, "m"),
+            ];
+            let parts: Vec<String> = units
+                .iter()
+                .skip_while(|(value, _)| *value == 0)
+                .take(2)
+                .filter(|(value, _)| *value > 0)
+                .map(|(value, unit)| format!("{}{}", value, unit))
+                .collect();
+            parts.join(" ")
+        }
+    }
+}
+
+fn format_urgency(urgency: f64, config: &Config) -> String {
+    let urgency = if config.clamp_negative_urgency {
+        urgency.max(0.0)
+    } else {
+        urgency
+    };
+    match config.urgency_scale {
+        Some(scale) => scale_urgency(urgency, scale).to_string(),
+        None => {
+            let formatted = format!("{:.2}", urgency);
+            match formatted.strip_suffix(".00") {
+                Some(whole) if config.urgency_trim_zeros => whole.to_string(),
+                _ => formatted,
+            }
+        }
+    }
+}
+
+fn format_priority(priority: &str, style: PriorityStyle) -> String {
+    match (style, priority) {
+        (PriorityStyle::Bang, "H") => "!!!".to_string(),
+        (PriorityStyle::Bang, "M") => "!!".to_string(),
+        (PriorityStyle::Bang, "L") => "!".to_string(),
+        (_, priority) => priority.to_string(),
+    }
+}
+
+fn field_label(field: Field, config: &Config) -> Option<&str> {
+    match field {
+        Field::Description | Field::Tags => None,
+        Field::Priority => Some(&config.priority_label),
+        Field::Due => Some(&config.due_label),
+        Field::Urgency => Some(&config.urgency_label),
+    }
+}
+
+fn field_key(field: Field) -> &'static str {
+    match field {
+        Field::Description => "description",
+        Field::Tags => "tags",
+        Field::Priority => "priority",
+        Field::Due => "due",
+        Field::Urgency => "urgency",
+    }
+}
+
+/// Maps `urgency` linearly onto 0-10, where `scale` is the raw urgency shown as 10.
+fn scale_urgency(urgency: f64, scale: f64) -> u8 {
+    if scale <= 0.0 {
+        return 0;
+    }
+    (urgency / scale * 10.0).round().clamp(0.0, 10.0) as u8
+}
+
+/// Maps `urgency` to waybar's `percentage`, reaching 100 at `full`. The log scale spreads
+/// the low urgencies most tasks have further apart. Negative urgencies count as 0.
+fn urgency_percentage(urgency: f64, full: f64, scale: PercentageScale) -> u8 {
+    if full <= 0.0 {
+        return 0;
+    }
+    let urgency = urgency.max(0.0);
+    let fraction = match scale {
+        PercentageScale::Linear => urgency / full,
+        PercentageScale::Log => urgency.ln_1p() / full.ln_1p(),
+    };
+    (fraction * 100.0).round().clamp(0.0, 100.0) as u8
+}
+
+/// Draws `urgency` relative to `max_urgency` as `[████░░]`, `width` cells wide.
+/// Tasks with zero or negative urgency get an empty bar.
+fn urgency_bar(urgency: f64, max_urgency: f64, width: usize) -> String {
+    let filled = if max_urgency > 0.0 && urgency > 0.0 {
+        ((urgency / max_urgency * width as f64).round() as usize).min(width)
+    } else {
+        0
+    };
+    format!("[{}{}]", "█".repeat(filled), "░".repeat(width - filled))
+}
+
+/// Joins the lines of a multi-line description so each task stays on one tooltip line.
+fn sanitize_newlines(description: &str, placeholder: &str) -> String {
+    description.lines().collect::<Vec<_>>().join(placeholder)
+}
+
+/// Trims the description with `trim_descriptions`, and also shrinks each run of whitespace
+/// inside it to a single space with `collapse_whitespace`.
+fn tidy_whitespace(description: String, config: &Config) -> String {
+    if config.collapse_whitespace {
+        description.split_whitespace().collect::<Vec<_>>().join(" ")
+    } else if config.trim_descriptions {
+        description.trim().to_string()
+    } else {
+        description
+    }
+}
+
+/// Doubles `%`, `{` and `}` so waybar does not take them for format placeholders.
+fn escape_placeholders(description: &str) -> String {
+    let mut escaped = String::with_capacity(description.len());
+    for c in description.chars() {
+        if matches!(c, '%' | '{' | '}') {
+            escaped.push(c);
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+pub fn generate_waybar_output_from_task_export(
+    config: &Config,
+) -> Result<WaybarOutput, TaskHookWaybarError> {
+    let tasks = load_tasks(default_task_source().as_deref(), config)?;
+    let waiting = if config.show_waiting_section {
+        call_waiting_export(config)?
+    } else {
+        Vec::new()
+    };
+    Ok(generate_waybar_output_with_waiting(
+        &tasks, &waiting, config,
+    ))
+}
+
+fn task_export_args(config: &Config, filter: &str) -> Vec<String> {
+    std::iter::once("rc.hooks:off")
+        .chain((!config.use_context).then_some("rc.context:none"))
+        .chain(filter.split_whitespace())
+        .chain(config.extra_task_args.iter().map(String::as_str))
+        .chain(std::iter::once("export"))
+        .map(String::from)
+        .collect()
+}
+
+/// Exports the tasks matching the config from taskwarrior, sorted by `config.sort`.
+pub fn call_task_export(config: &Config) -> Result<Vec<Task>, TaskHookWaybarError> {
+    export_tasks(config, &config.filter)
+}
+
+/// A task as a task database stores it: its working set index (0 if it has none), its
+/// uuid and its properties, with dates as unix timestamps like `1733495402`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TaskRecord {
+    pub id: u32,
+    pub uuid: String,
+    pub properties: HashMap<String, String>,
+}
+
+/// Reads the pending tasks straight from a task database, which is faster than spawning
+/// `task export`.
+pub trait TaskSource {
+    /// The records of the pending tasks, `None` if there is no database to read.
+    fn pending_records(&self) -> Result<Option<Vec<TaskRecord>>, TaskHookWaybarError>;
+}
+
+/// The [`TaskSource`] of the build: the taskchampion replica read by taskwarrior 3 with the
+/// `taskchampion` feature, none without it.
+pub fn default_task_source() -> Option<Box<dyn TaskSource>> {
+    #[cfg(feature = "taskchampion")]
+    return crate::replica::ReplicaSource::default_location()
+        .map(|source| Box::new(source) as Box<dyn TaskSource>);
+    #[cfg(not(feature = "taskchampion"))]
+    None
+}
+
+/// The pending tasks from `source`, sorted by `config.sort`. Falls back to
+/// [`call_task_export`] without a source, when the source has no database, or when the
+/// config needs taskwarrior itself: `use_context`, a `filter` other than the default, or
+/// `extra_task_args`.
+///
+/// Tasks read from a source get the urgency taskwarrior would export with its default
+/// coefficients, see [`record_urgencies`].
+pub fn load_tasks(
+    source: Option<&dyn TaskSource>,
+    config: &Config,
+) -> Result<Vec<Task>, TaskHookWaybarError> {
+    load_tasks_at(source, config, Local::now())
+}
+
+fn load_tasks_at(
+    source: Option<&dyn TaskSource>,
+    config: &Config,
+    now: DateTime<Local>,
+) -> Result<Vec<Task>, TaskHookWaybarError> {
+    let needs_taskwarrior = config.use_context
+        || config.filter.trim() != PENDING_FILTER
+        || !config.extra_task_args.is_empty();
+    let records = match source.filter(|_| !needs_taskwarrior) {
+        Some(source) => source.pending_records()?,
+        None => None,
+    };
+    let Some(records) = records else {
+        return call_task_export(config);
+    };
+
+    // Waiting tasks are pending ones whose wait date is still ahead, status:pending leaves
+    // them out.
+    let now = now.timestamp();
+    let records: Vec<TaskRecord> = records
+        .into_iter()
+        .filter(|record| {
+            record
+                .properties
+                .get("wait")
+                .and_then(|wait| wait.parse::<i64>().ok())
+                .is_none_or(|wait| wait <= now)
+        })
+        .collect();
+    let urgencies = record_urgencies(&records, now);
+    let mut tasks: Vec<Task> = records
         .into_iter()
-        .flatten()
+        .zip(urgencies)
+        .map(|(record, urgency)| Task {
+            urgency: Some(urgency),
+            ..Task::from(record)
+        })
         .collect();
+    sort_tasks_by(&mut tasks, config.sort);
+    Ok(tasks)
+}
+
+impl From<TaskRecord> for Task {
+    fn from(record: TaskRecord) -> Self {
+        let property = |name: &str| record.properties.get(name).cloned();
+        let date = |name: &str| {
+            record
+                .properties
+                .get(name)
+                .and_then(|value| record_date(value))
+        };
+        let mut tags: Vec<String> = record
+            .properties
+            .keys()
+            .filter_map(|key| key.strip_prefix("tag_"))
+            .map(String::from)
+            .collect();
+        tags.sort();
+        let mut annotations: Vec<(i64, Annotation)> = record
+            .properties
+            .iter()
+            .filter_map(|(key, description)| {
+                let timestamp = key.strip_prefix("annotation_")?;
+                let annotation = Annotation {
+                    entry: record_date(timestamp),
+                    description: description.clone(),
+                };
+                Some((timestamp.parse().ok()?, annotation))
+            })
+            .collect();
+        annotations.sort_by_key(|(timestamp, _)| *timestamp);
 
-        [self.id.to_string(), parts.join(", ")].join(" ")
+        Task {
+            id: record.id,
+            status: property("status"),
+            description: property("description"),
+            priority: property("priority"),
+            due: date("due"),
+            scheduled: date("scheduled"),
+            urgency: None,
+            recur: property("recur"),
+            start: date("start"),
+            tags,
+            entry: date("entry"),
+            modified: date("modified"),
+            annotations: annotations.into_iter().map(|(_, a)| a).collect(),
+            uuid: Some(record.uuid),
+        }
     }
 }
 
-pub fn generate_waybar_output_from_task_export() -> Result<WaybarOutput, TaskHookWaybarError> {
-    Ok(generate_waybar_output(&call_task_export()?))
+/// A unix timestamp of a [`TaskRecord`] as the `YYYYMMDDThhmmssZ` of `task export`.
+fn record_date(timestamp: &str) -> Option<String> {
+    let datetime = chrono::DateTime::from_timestamp(timestamp.parse().ok()?, 0)?;
+    Some(datetime.format("%Y%m%dT%H%M%SZ").to_string())
 }
 
-fn call_task_export() -> Result<Vec<Task>, TaskHookWaybarError> {
+/// Exports the waiting tasks for `show_waiting_section`, sorted like the pending ones.
+pub fn call_waiting_export(config: &Config) -> Result<Vec<Task>, TaskHookWaybarError> {
+    export_tasks(config, WAITING_FILTER)
+}
+
+fn export_tasks(config: &Config, filter: &str) -> Result<Vec<Task>, TaskHookWaybarError> {
     let output = Command::new("task")
-        .arg("rc.hooks:off")
-        .arg("status:pending")
-        .arg("export")
+        .args(task_export_args(config, filter))
         .output()?;
 
     let json_output = String::from_utf8_lossy(&output.stdout);
-    let mut tasks: Vec<Task> = serde_json::from_str(&json_output)?;
+    let mut tasks = parse_export(&json_output)?;
 
-    sort_tasks(&mut tasks);
+    sort_tasks_by(&mut tasks, config.sort);
 
     Ok(tasks)
 }
 
-fn sort_tasks(tasks: &mut [Task]) -> &mut [Task] {
-    tasks.sort_unstable_by(|a, b| {
-        b.urgency
-            .partial_cmp(&a.urgency)
-            .unwrap_or(std::cmp::Ordering::Equal)
-            .then_with(|| compare_optional_timestamps(a.due.as_deref(), b.due.as_deref()))
-            .then_with(|| a.id.cmp(&b.id))
+/// How many characters of the export are shown on each side of a parse error.
+const SNIPPET_CONTEXT: usize = 30;
+
+/// Parses the json of `task export`, pointing at the offending part if it is malformed.
+fn parse_export(json_output: &str) -> Result<Vec<Task>, TaskHookWaybarError> {
+    serde_json::from_str(json_output).map_err(|source| TaskHookWaybarError::ExportJson {
+        line: source.line(),
+        column: source.column(),
+        snippet: snippet_at(json_output, source.line(), source.column()),
+        source,
+    })
+}
+
+/// The text around the 1-based `line` and `column`, with `⟨here⟩` marking the position.
+fn snippet_at(text: &str, line: usize, column: usize) -> String {
+    let Some(line) = text.lines().nth(line.saturating_sub(1)) else {
+        return String::new();
+    };
+    let chars: Vec<char> = line.chars().collect();
+    let position = column.saturating_sub(1).min(chars.len());
+    let start = position.saturating_sub(SNIPPET_CONTEXT);
+    let end = (position + SNIPPET_CONTEXT).min(chars.len());
+    format!(
+        "{}⟨here⟩{}",
+        chars[start..position].iter().collect::<String>(),
+        chars[position..end].iter().collect::<String>()
+    )
+}
+
+/// A task together with its dates, parsed once before sorting.
+struct SortEntry {
+    due: Option<DateTime<Local>>,
+    created: Option<DateTime<Local>>,
+    task: Task,
+}
+
+pub fn sort_tasks(tasks: &mut Vec<Task>) -> &mut Vec<Task> {
+    sort_tasks_by(tasks, SortKey::Urgency)
+}
+
+/// Sorts by `key`, ties (and `SortKey::Urgency`) fall back to urgency, due date, id and uuid.
+pub fn sort_tasks_by(tasks: &mut Vec<Task>, key: SortKey) -> &mut Vec<Task> {
+    let mut entries: Vec<SortEntry> = tasks
+        .drain(..)
+        .map(|task| SortEntry {
+            due: task.due.as_deref().and_then(|d| parse_due_date(d).ok()),
+            created: match key {
+                SortKey::Urgency => None,
+                SortKey::Newest | SortKey::Oldest => task
+                    .entry
+                    .as_deref()
+                    .or(task.modified.as_deref())
+                    .and_then(|d| parse_due_date(d).ok()),
+            },
+            task,
+        })
+        .collect();
+
+    entries.sort_unstable_by(|a, b| {
+        let by_key = match key {
+            SortKey::Urgency => Ordering::Equal,
+            SortKey::Newest => compare_missing_last(a.created.map(Reverse), b.created.map(Reverse)),
+            SortKey::Oldest => compare_missing_last(a.created, b.created),
+        };
+        by_key.then_with(|| {
+            b.task
+                .urgency
+                .partial_cmp(&a.task.urgency)
+                .unwrap_or(Ordering::Equal)
+                .then_with(|| a.due.cmp(&b.due))
+                .then_with(|| a.task.id.cmp(&b.task.id))
+                .then_with(|| a.task.uuid.cmp(&b.task.uuid))
+        })
     });
+
+    tasks.extend(entries.into_iter().map(|entry| entry.task));
     tasks
 }
 
-fn compare_optional_timestamps(a: Option<&str>, b: Option<&str>) -> std::cmp::Ordering {
-    a.and_then(|s| parse_due_date(s).ok())
-        .cmp(&b.and_then(|s| parse_due_date(s).ok()))
+fn compare_missing_last<T: Ord>(a: Option<T>, b: Option<T>) -> Ordering {
+    match (a, b) {
+        (Some(a), Some(b)) => a.cmp(&b),
+        (Some(_), None) => Ordering::Less,
+        (None, Some(_)) => Ordering::Greater,
+        (None, None) => Ordering::Equal,
+    }
+}
+
+pub fn generate_waybar_output(tasks: &[Task], config: &Config) -> WaybarOutput {
+    generate_waybar_output_at(tasks, &[], config, Local::now())
 }
 
-fn generate_waybar_output(tasks: &[Task]) -> WaybarOutput {
-    if let Some(most_urgent) = tasks.first() {
-        let tooltip = tasks
+/// Like [`generate_waybar_output`], with the `waiting` tasks in their own tooltip section.
+pub fn generate_waybar_output_with_waiting(
+    tasks: &[Task],
+    waiting: &[Task],
+    config: &Config,
+) -> WaybarOutput {
+    generate_waybar_output_at(tasks, waiting, config, Local::now())
+}
+
+fn generate_waybar_output_at(
+    tasks: &[Task],
+    waiting: &[Task],
+    config: &Config,
+    now: DateTime<Local>,
+) -> WaybarOutput {
+    let kept: Cow<[Task]> = match config.min_urgency {
+        Some(min) => tasks
             .iter()
-            .map(Task::construct_task_output)
-            .collect::<Vec<_>>()
-            .join("\n");
+            .filter(|task| task.urgency.unwrap_or(0.0) >= min)
+            .cloned()
+            .collect(),
+        None => Cow::Borrowed(tasks),
+    };
+    let empty_text = if kept.is_empty() && !tasks.is_empty() {
+        config.all_filtered_text.as_str()
+    } else {
+        "No tasks."
+    };
+    let tasks: &[Task] = &kept;
+    let total = tasks.len();
+    let tasks = match config.max_tasks {
+        Some(max_tasks) => &tasks[..total.min(max_tasks)],
+        None => tasks,
+    };
+    if let Some(most_urgent) = tasks.first() {
+        let skip = if config.tooltip_skip_top && tasks.len() > 1 {
+            1
+        } else {
+            0
+        };
+        let tooltip_tasks = tooltip_order(&tasks[skip..], config.tooltip_sort);
+        let tooltip = match config.tooltip_format {
+            TooltipFormat::Text => {
+                let mut tooltip =
+                    String::with_capacity((tasks.len() + waiting.len()) * ESTIMATED_LINE_LEN);
+                write_tooltip_lines(&mut tooltip, &tooltip_tasks, config, now);
+                write_waiting_section(&mut tooltip, waiting, config, now);
+                if config.tag_summary {
+                    if let Some(summary) = tag_summary(tasks, config.tag_summary_include_virtual) {
+                        tooltip.push('\n');
+                        tooltip.push_str(&summary);
+                    }
+                }
+                match config.max_tooltip_bytes {
+                    Some(max_bytes) => cap_tooltip(tooltip, max_bytes, config.pango_markup),
+                    None => tooltip,
+                }
+            }
+            TooltipFormat::Json => tooltip_json(&tooltip_tasks, config, now),
+        };
+
+        let mut text = match (config.text_mode, tied_top_urgency(tasks, config)) {
+            (TextMode::Count, _) => task_count(total),
+            (TextMode::Task, Some((count, urgency))) => {
+                format!("{} tasks at urgency {:.1}", count, urgency)
+            }
+            (TextMode::Task, None) => {
+                let shown = text_task(tasks, config, now);
+                let next = tasks.iter().find(|task| !std::ptr::eq(*task, shown));
+                render_text_with_next(shown, next, config, now)
+            }
+        };
+        if config.show_count && config.text_mode == TextMode::Task {
+            let _ = write!(text, " ({})", task_count(total));
+        }
 
         WaybarOutput {
-            text: most_urgent.construct_task_output(),
-            tooltip,
+            text,
+            tooltip: non_empty_tooltip(tooltip),
+            class: Some(compute_class(most_urgent, tasks, config, now)),
+            percentage: config.percentage_urgency.map(|full| {
+                urgency_percentage(
+                    most_urgent.urgency.unwrap_or(0.0),
+                    full,
+                    config.percentage_scale,
+                )
+            }),
+            alt: compute_alt(most_urgent, config, now).map(String::from),
         }
     } else {
+        let tooltip = match config.tooltip_format {
+            TooltipFormat::Text => {
+                let mut tooltip = empty_text.to_string();
+                write_waiting_section(&mut tooltip, waiting, config, now);
+                tooltip
+            }
+            TooltipFormat::Json => tooltip_json(&[], config, now),
+        };
+        let text = match config.text_mode {
+            TextMode::Task => empty_text.to_string(),
+            TextMode::Count => task_count(0),
+        };
         WaybarOutput {
-            text: "No tasks.".to_string(),
-            tooltip: "No tasks.".to_string(),
+            text,
+            tooltip: Some(tooltip),
+            class: None,
+            percentage: None,
+            alt: None,
+        }
+    }
+}
+
+/// The tooltip tasks re-sorted by `sort`. Ties keep their order from `config.sort`.
+fn tooltip_order(tasks: &[Task], sort: Option<TooltipSort>) -> Cow<'_, [Task]> {
+    let Some(sort) = sort else {
+        return Cow::Borrowed(tasks);
+    };
+    let mut sorted = tasks.to_vec();
+    match sort {
+        TooltipSort::Urgency => {
+            sort_tasks_by(&mut sorted, SortKey::Urgency);
         }
+        TooltipSort::Due => sorted.sort_by_cached_key(|task| {
+            let due = task.due.as_deref().and_then(|d| parse_due_date(d).ok());
+            (due.is_none(), due)
+        }),
+        TooltipSort::Priority => sorted.sort_by_key(|task| match task.priority.as_deref() {
+            Some("H") => 0,
+            Some("M") => 1,
+            Some("L") => 2,
+            _ => 3,
+        }),
     }
+    Cow::Owned(sorted)
 }
 
-fn parse_due_date(due: &str) -> Result<DateTime<Local>, chrono::ParseError> {
-    let due_formatted = format!(
-        "{}-{}-{}T{}:{}:{}+00:00",
-        &due[0..4],   // Year
-        &due[4..6],   // Month
-        &due[6..8],   // Day
-        &due[9..11],  // Hour
-        &due[11..13], // Minute
-        &due[13..15]  // Second
-    );
+/// Cuts `tooltip` down to at most `max_bytes` including the `…` marking the cut. With pango
+/// markup it is cut after the last whole line, so no tag or escape is left open.
+fn cap_tooltip(tooltip: String, max_bytes: usize, pango_markup: bool) -> String {
+    const ELLIPSIS: &str = "…";
+    if tooltip.len() <= max_bytes {
+        return tooltip;
+    }
+    let budget = max_bytes.saturating_sub(ELLIPSIS.len());
+    let mut kept = prefix_within_bytes(&tooltip, budget);
+    if pango_markup {
+        kept = kept.rfind('\n').map_or("", |end| &tooltip[..=end]);
+    }
+    let mut capped = kept.to_string();
+    if capped.len() + ELLIPSIS.len() <= max_bytes {
+        capped.push_str(ELLIPSIS);
+    }
+    capped
+}
 
-    let datetime = DateTime::parse_from_rfc3339(&due_formatted)?;
-    Ok(datetime.with_timezone(&Local))
+fn task_count(count: usize) -> String {
+    format!("{} {}", count, if count == 1 { "task" } else { "tasks" })
 }
 
-pub fn write_waybar_json(
-    output: &WaybarOutput,
-    json_path: &PathBuf,
+/// Renders the already exported tasks once more for each of `extra_outputs` and writes
+/// them next to the main output.
+pub fn write_extra_outputs(
+    tasks: &[Task],
+    waiting: &[Task],
+    config: &Config,
 ) -> Result<(), TaskHookWaybarError> {
-    let file = OpenOptions::new()
-        .write(true)
-        .create(true)
-        .truncate(true)
-        .open(json_path)?;
+    for extra in &config.extra_outputs {
+        let extra_config = Config {
+            text_mode: extra.text_mode,
+            ..config.clone()
+        };
+        let output = generate_waybar_output_with_waiting(tasks, waiting, &extra_config);
+        FileSink::new(extra.output.clone(), config.pretty_output)
+            .with_target(config.output_target, config.pango_markup)
+            .write(&output)?;
+    }
+    Ok(())
+}
 
-    info!("Opened file at {}", json_path.display());
+/// The tasks as a json array, written as a string into the tooltip.
+fn tooltip_json(tasks: &[Task], config: &Config, now: DateTime<Local>) -> String {
+    let plain = Config {
+        pango_markup: false,
+        ..config.clone()
+    };
+    let entries: Vec<serde_json::Value> = tasks
+        .iter()
+        .map(|task| task.to_tooltip_json(&plain, now))
+        .collect();
+    serde_json::Value::from(entries).to_string()
+}
 
-    let mut writer = BufWriter::new(file);
-    let json_output = serde_json::to_string(output)?;
+/// Writes one line per task, each prefixed by its urgency bar if enabled.
+fn write_tooltip_lines(
+    tooltip: &mut String,
+    tasks: &[Task],
+    config: &Config,
+    now: DateTime<Local>,
+) {
+    let max_urgency = tasks
+        .iter()
+        .filter_map(|task| task.urgency)
+        .fold(0.0, f64::max);
+    for (i, task) in tasks.iter().enumerate() {
+        if i > 0 {
+            tooltip.push('\n');
+        }
+        if config.urgency_bar {
+            let bar = urgency_bar(task.urgency.unwrap_or(0.0), max_urgency, URGENCY_BAR_WIDTH);
+            let _ = write!(tooltip, "{} ", bar);
+        }
+        task.write_task_output(tooltip, config, Line::Tooltip, now);
+    }
+}
 
-    writeln!(writer, "{}", json_output)?;
+/// Appends the waiting tasks under a heading, if there are any.
+fn write_waiting_section(
+    tooltip: &mut String,
+    waiting: &[Task],
+    config: &Config,
+    now: DateTime<Local>,
+) {
+    if waiting.is_empty() {
+        return;
+    }
+    if !tooltip.is_empty() {
+        tooltip.push('\n');
+    }
+    tooltip.push_str(WAITING_HEADING);
+    tooltip.push('\n');
+    write_tooltip_lines(tooltip, waiting, config, now);
+}
 
-    info!("Json written to file");
+/// The task at `rotation_index` among the first `rotate_top`, else the first task due within
+/// `text_due_within_days`, else the first task.
+fn text_task<'a>(tasks: &'a [Task], config: &Config, now: DateTime<Local>) -> &'a Task {
+    if let Some(top) = config.rotate_top.filter(|top| *top > 0) {
+        return &tasks[config.rotation_index % top.min(tasks.len())];
+    }
+    config
+        .text_due_within_days
+        .and_then(|days| {
+            tasks
+                .iter()
+                .find(|task| task.is_due_within(days, config, now))
+        })
+        .unwrap_or(&tasks[0])
+}
 
-    Ok(())
+/// The bar text of `top`, followed by a short hint at `next` if `show_next` is set.
+fn render_text_with_next(
+    top: &Task,
+    next: Option<&Task>,
+    config: &Config,
+    now: DateTime<Local>,
+) -> String {
+    let mut text = top.render_text(config, now);
+    if let Some(elapsed) = top
+        .active_elapsed(now)
+        .filter(|_| config.show_active_elapsed)
+    {
+        let _ = write!(text, " ▶ {}", elapsed);
+    }
+    let hint = next
+        .filter(|_| config.show_next)
+        .and_then(|next| next.description.as_deref());
+    if let Some(description) = hint {
+        let description = truncate_chars(
+            &tidy_whitespace(
+                sanitize_newlines(description, &config.newline_placeholder),
+                config,
+            ),
+            NEXT_HINT_MAX_LEN,
+        );
+        let description = if config.escape_placeholders {
+            escape_placeholders(&description)
+        } else {
+            description
+        };
+        let description = if config.pango_markup {
+            escape_markup(&description)
+        } else {
+            description
+        };
+        let _ = write!(text, " → (next: {})", description);
+    }
+    text
 }
 
-#[cfg(debug_assertions)]
-pub mod debug {
-    use super::WaybarOutput;
-    pub fn print_output(output: &WaybarOutput) -> Result<(), serde_json::Error> {
-        let json_output = serde_json::to_string_pretty(output)?;
-        println!("{}", json_output);
-        Ok(())
+/// With `text_tie_mode = "count"`, how many tasks share the urgency of the first task and
+/// that urgency, if there is more than one.
+fn tied_top_urgency(tasks: &[Task], config: &Config) -> Option<(usize, f64)> {
+    if config.text_tie_mode != TextTieMode::Count {
+        return None;
     }
+    let urgency = tasks.first()?.urgency?;
+    let count = tasks
+        .iter()
+        .filter(|task| task.urgency == Some(urgency))
+        .count();
+    (count > 1).then_some((count, urgency))
 }
 
-#[cfg(test)]
-pub mod tests {
-    use super::*;
-    use chrono::TimeZone;
+/// Counts tasks per tag as `+tag:n`, most used first and alphabetically on ties.
+///
+/// Virtual tags such as `+PENDING` start with an uppercase letter and are skipped
+/// unless `include_virtual` is set.
+fn tag_summary(tasks: &[Task], include_virtual: bool) -> Option<String> {
+    let mut counts: std::collections::HashMap<&str, usize> = std::collections::HashMap::new();
+    for tag in tasks.iter().flat_map(|task| &task.tags) {
+        if include_virtual || !tag.starts_with(|c: char| c.is_uppercase()) {
+            *counts.entry(tag.as_str()).or_default() += 1;
+        }
+    }
+    if counts.is_empty() {
+        return None;
+    }
 
-    #[test]
-    fn test_parse_due_date_valid() {
-        let due = "20241206T143002Z";
-        let result = parse_due_date(due);
-        assert!(result.is_ok());
-        assert_eq!(
-            result
-                .unwrap()
-                .with_timezone(&chrono::Utc)
-                .format("%a, %y-%m-%d %H:%M")
-                .to_string(),
-            "Fri, 24-12-06 14:30"
-        );
+    let mut counts: Vec<_> = counts.into_iter().collect();
+    counts.sort_unstable_by(|(tag_a, count_a), (tag_b, count_b)| {
+        count_b.cmp(count_a).then_with(|| tag_a.cmp(tag_b))
+    });
+    Some(
+        counts
+            .iter()
+            .map(|(tag, count)| format!("+{}:{}", tag, count))
+            .collect::<Vec<_>>()
+            .join(" "),
+    )
+}
+
+/// Builds the space-separated class list from the top task's urgency and due state,
+/// and whether any task is started.
+fn compute_class(
+    most_urgent: &Task,
+    tasks: &[Task],
+    config: &Config,
+    now: DateTime<Local>,
+) -> String {
+    let urgency = most_urgent.urgency.unwrap_or(0.0);
+    let urgency_class = match config.previous_urgency_class.as_deref() {
+        Some(previous) if config.class_hysteresis > 0.0 => {
+            urgency_class_with_hysteresis(urgency, previous, config)
+        }
+        _ => urgency_class(urgency, config),
+    };
+    let mut classes = vec![urgency_class];
+    classes.extend(due_state(most_urgent, now));
+    if tasks.iter().any(|task| task.start.is_some()) {
+        classes.push("active");
     }
+    classes.join(" ")
+}
 
-    #[test]
-    fn test_generate_valid_waybar_output() {
-        let waybar_output = generate_waybar_output(&[
-            Task {
-                id: 1,
-                description: Some("Test1".to_string()),
-                priority: Some("H".to_string()),
-                due: Some("20241206T143002Z".to_string()),
-                urgency: Some(42.0),
-            },
-            Task {
-                id: 2,
-                description: Some("Test2".to_string()),
-                priority: Some("M".to_string()),
-                due: Some("20241206T173002Z".to_string()),
-                urgency: Some(5.0),
-            },
-        ]);
+fn compute_alt(most_urgent: &Task, config: &Config, now: DateTime<Local>) -> Option<&'static str> {
+    match config.alt_source {
+        AltSource::Due => due_state(most_urgent, now),
+        AltSource::Priority => Some(priority_state(most_urgent)),
+    }
+}
+
+/// The top task's priority for `alt_source = "priority"`, so waybar can pick an icon by it.
+fn priority_state(most_urgent: &Task) -> &'static str {
+    match most_urgent.priority.as_deref() {
+        Some("H") => "high",
+        Some("M") => "medium",
+        Some("L") => "low",
+        _ => "none",
+    }
+}
+
+/// The top task's due state, used both as class and as `alt`.
+fn due_state(most_urgent: &Task, now: DateTime<Local>) -> Option<&'static str> {
+    most_urgent.is_due_today(now).then_some("due-today")
+}
+
+const URGENCY_CLASSES: [&str; 3] = ["urgency-low", "urgency-medium", "urgency-high"];
+
+/// Like [`urgency_class`], but only leaves the `previous` class once `urgency` is more than
+/// `class_hysteresis` past a threshold, so an urgency hovering around it does not flicker.
+fn urgency_class_with_hysteresis(urgency: f64, previous: &str, config: &Config) -> &'static str {
+    let Some(previous_level) = URGENCY_CLASSES.iter().position(|class| *class == previous) else {
+        return urgency_class(urgency, config);
+    };
+    let thresholds = [
+        config.medium_urgency_threshold,
+        config.high_urgency_threshold,
+    ];
+    let level = thresholds
+        .iter()
+        .enumerate()
+        .filter(|(i, threshold)| {
+            // Thresholds above the previous level are harder to reach, those below harder to drop under.
+            let margin = if *i >= previous_level {
+                config.class_hysteresis
+            } else {
+                -config.class_hysteresis
+            };
+            urgency >= **threshold + margin
+        })
+        .count();
+    URGENCY_CLASSES[level]
+}
+
+/// The urgency class in a previously written output, to keep it with `class_hysteresis`.
+pub fn read_previous_urgency_class(path: &Path) -> Option<String> {
+    let content = std::fs::read_to_string(path).ok()?;
+    let output: serde_json::Value = serde_json::from_str(content.lines().next()?).ok()?;
+    let classes: Vec<&str> = match &output["class"] {
+        serde_json::Value::String(class) => class.split_whitespace().collect(),
+        serde_json::Value::Array(classes) => classes.iter().filter_map(|c| c.as_str()).collect(),
+        _ => return None,
+    };
+    classes
+        .into_iter()
+        .find(|class| URGENCY_CLASSES.contains(class))
+        .map(String::from)
+}
+
+/// The id and urgency of a task, kept from one run to the next for `log_changes`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TaskSnapshot {
+    id: u32,
+    urgency: f64,
+}
+
+pub fn snapshot_tasks(tasks: &[Task]) -> Vec<TaskSnapshot> {
+    tasks
+        .iter()
+        .map(|task| TaskSnapshot {
+            id: task.id,
+            urgency: task.urgency.unwrap_or(0.0),
+        })
+        .collect()
+}
+
+/// The snapshot written by the previous run, `None` if there is none.
+pub fn read_snapshot(path: &Path) -> Option<Vec<TaskSnapshot>> {
+    serde_json::from_str(&std::fs::read_to_string(path).ok()?).ok()
+}
+
+pub fn write_snapshot(path: &Path, snapshot: &[TaskSnapshot]) -> Result<(), TaskHookWaybarError> {
+    std::fs::write(path, serde_json::to_string(snapshot)?)
+        .map_err(TaskHookWaybarError::with_path(path))
+}
+
+/// Moves the `rotate_top` index kept at `path` on by one, wrapping after `top` tasks, and
+/// returns the new index. A missing or unreadable file starts over at 0.
+pub fn advance_rotation(path: &Path, top: usize) -> Result<usize, TaskHookWaybarError> {
+    let index = match read_rotation(path) {
+        Some(previous) if top > 0 => (previous + 1) % top,
+        _ => 0,
+    };
+    std::fs::write(path, index.to_string()).map_err(TaskHookWaybarError::with_path(path))?;
+    Ok(index)
+}
+
+/// The `rotate_top` index of the previous run, `None` if there is none.
+pub fn read_rotation(path: &Path) -> Option<usize> {
+    std::fs::read_to_string(path).ok()?.trim().parse().ok()
+}
+
+/// How the task list changed between two runs.
+#[derive(Debug, Default, PartialEq)]
+pub struct TaskDiff {
+    pub added: usize,
+    pub removed: usize,
+    /// Tasks in both lists that moved relative to the others or changed urgency.
+    pub reordered: usize,
+}
+
+impl std::fmt::Display for TaskDiff {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if *self == TaskDiff::default() {
+            return write!(f, "no changes");
+        }
+        write!(
+            f,
+            "+{} {}, -{} {}, {} reordered",
+            self.added,
+            if self.added == 1 { "task" } else { "tasks" },
+            self.removed,
+            if self.removed == 1 { "task" } else { "tasks" },
+            self.reordered
+        )
+    }
+}
+
+/// Compares two task lists by id, each in the order they were shown.
+pub fn diff_tasks(old: &[TaskSnapshot], new: &[TaskSnapshot]) -> TaskDiff {
+    let old_common: Vec<&TaskSnapshot> = old
+        .iter()
+        .filter(|task| new.iter().any(|t| t.id == task.id))
+        .collect();
+    let new_common: Vec<&TaskSnapshot> = new
+        .iter()
+        .filter(|task| old.iter().any(|t| t.id == task.id))
+        .collect();
+    let reordered = old_common
+        .iter()
+        .zip(&new_common)
+        .filter(|(old_task, new_task)| {
+            old_task.id != new_task.id
+                || new_common
+                    .iter()
+                    .any(|t| t.id == old_task.id && t.urgency != old_task.urgency)
+        })
+        .count();
+
+    TaskDiff {
+        added: new.len() - new_common.len(),
+        removed: old.len() - old_common.len(),
+        reordered,
+    }
+}
+
+fn urgency_class(urgency: f64, config: &Config) -> &'static str {
+    if urgency >= config.high_urgency_threshold {
+        "urgency-high"
+    } else if urgency >= config.medium_urgency_threshold {
+        "urgency-medium"
+    } else {
+        "urgency-low"
+    }
+}
+
+/// Parses UTC dates in taskwarrior's `YYYYMMDDThhmmssZ` form, and in the forms in
+/// [`LENIENT_DATE_FORMATS`]: with fractional seconds like `20241206T143002.500Z`, or with
+/// separators like `2024-12-06T14:30:02Z`. Anything else is rejected.
+pub fn parse_due_date(due: &str) -> Result<DateTime<Local>, InvalidDateError> {
+    let bytes = due.as_bytes();
+    let is_valid_shape = bytes.len() == 16
+        && bytes[8] == b'T'
+        && bytes[15] == b'Z'
+        && bytes[..8]
+            .iter()
+            .chain(&bytes[9..15])
+            .all(u8::is_ascii_digit);
+    if !is_valid_shape {
+        return parse_lenient_date(due);
+    }
+
+    let datetime = NaiveDateTime::parse_from_str(due, "%Y%m%dT%H%M%SZ")?;
+    Ok(Local.from_utc_datetime(&datetime))
+}
+
+/// UTC dates in the forms other tools write: with fractional seconds like
+/// `20241206T143002.500Z`, or with separators like `2024-12-06T14:30:02Z`.
+const LENIENT_DATE_FORMATS: [&str; 2] = ["%Y%m%dT%H%M%S%.fZ", "%Y-%m-%dT%H:%M:%S%.fZ"];
+
+fn parse_lenient_date(due: &str) -> Result<DateTime<Local>, InvalidDateError> {
+    LENIENT_DATE_FORMATS
+        .iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(due, format).ok())
+        .map(|datetime| Local.from_utc_datetime(&datetime))
+        .ok_or_else(|| InvalidDateError::Format(due.to_string()))
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::config::ExtraOutput;
+    use crate::output::write_waybar_json;
+    use crate::task_colors::TaskColors;
+    use proptest::prelude::*;
+
+    #[test]
+    fn test_parse_due_date_valid() {
+        let due = "20241206T143002Z";
+        let result = parse_due_date(due);
+        assert!(result.is_ok());
+        assert_eq!(
+            result
+                .unwrap()
+                .with_timezone(&chrono::Utc)
+                .format("%a, %y-%m-%d %H:%M")
+                .to_string(),
+            "Fri, 24-12-06 14:30"
+        );
+    }
+
+    #[test]
+    fn test_parse_due_date_lenient_forms() {
+        let standard = parse_due_date("20241206T143002Z").unwrap();
+        let with_millis = parse_due_date("20241206T143002.500Z").unwrap();
+        assert_eq!(with_millis - standard, chrono::TimeDelta::milliseconds(500));
+        assert_eq!(parse_due_date("2024-12-06T14:30:02Z").unwrap(), standard);
+        assert_eq!(
+            parse_due_date("2024-12-06T14:30:02.25Z").unwrap() - standard,
+            chrono::TimeDelta::milliseconds(250)
+        );
+
+        for due in [
+            "20241206T143002.Z",
+            "20241206T143002.500",
+            "2024-12-06 14:30:02Z",
+        ] {
+            assert!(
+                matches!(parse_due_date(due), Err(InvalidDateError::Format(_))),
+                "{}",
+                due
+            );
+        }
+    }
+
+    #[test]
+    fn test_parse_export_error_has_snippet() {
+        let export = r#"[{"id":1,"description":"Buy milk","urgency":2.5}"#.to_string()
+            + "\n"
+            + r#",{"id":2,"description":"Call bank" "urgency":1.0}]"#;
+        let error = parse_export(&export).unwrap_err();
+        match &error {
+            TaskHookWaybarError::ExportJson { line, snippet, .. } => {
+                assert_eq!(*line, 2);
+                assert_eq!(
+                    snippet,
+                    r#"":2,"description":"Call bank" ⟨here⟩"urgency":1.0}]"#
+                );
+            }
+            other => panic!("Expected ExportJson, got {:?}", other),
+        }
+        assert!(error.to_string().contains("line 2 column 36"), "{}", error);
+
+        assert_eq!(parse_export("[]").unwrap(), Vec::new());
+        assert!(matches!(
+            parse_export(""),
+            Err(TaskHookWaybarError::ExportJson { .. })
+        ));
+    }
+
+    #[test]
+    fn test_task_export_args_use_filter() {
+        let config = Config {
+            filter: "status:pending  project:work".to_string(),
+            ..Config::default()
+        };
+        assert_eq!(
+            task_export_args(&config, &config.filter),
+            ["rc.hooks:off", "status:pending", "project:work", "export"]
+        );
+    }
+
+    #[test]
+    fn test_task_export_args_with_extra_args() {
+        let config = Config {
+            extra_task_args: vec![
+                "rc.verbose:nothing".to_string(),
+                "project:home or project:garden".to_string(),
+            ],
+            ..Config::default()
+        };
+        assert_eq!(
+            task_export_args(&config, &config.filter),
+            [
+                "rc.hooks:off",
+                "status:pending",
+                "rc.verbose:nothing",
+                "project:home or project:garden",
+                "export"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_task_export_args_without_context() {
+        let config = Config {
+            use_context: false,
+            ..Config::default()
+        };
+        assert_eq!(
+            task_export_args(&config, &config.filter),
+            [
+                "rc.hooks:off",
+                "rc.context:none",
+                "status:pending",
+                "export"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_due_date_invalid() {
+        for due in [
+            "",
+            "2024",
+            "20241206",
+            "20241206 143002Z",
+            "2024120aT143002Z",
+        ] {
+            assert!(
+                matches!(parse_due_date(due), Err(InvalidDateError::Format(_))),
+                "{}",
+                due
+            );
+        }
+        assert!(matches!(
+            parse_due_date("20241306T143002Z"),
+            Err(InvalidDateError::Parse(_))
+        ));
+    }
+
+    proptest! {
+        #[test]
+        fn test_parse_due_date_never_panics(due in "\\PC*") {
+            if parse_due_date(&due).is_ok() {
+                prop_assert!(due.contains('T') && due.ends_with('Z'));
+            }
+        }
+
+        #[test]
+        fn test_parse_due_date_near_valid(due in "[0-9]{8}[T ]?[0-9]{6}Z?") {
+            let is_valid_shape = due.len() == 16 && due.ends_with('Z') && due.contains('T');
+            if !is_valid_shape {
+                prop_assert!(parse_due_date(&due).is_err());
+            }
+        }
+
+        #[test]
+        fn test_parse_due_date_valid_timestamps(
+            year in 1970i32..2100,
+            month in 1u32..=12,
+            day in 1u32..=28,
+            hour in 0u32..24,
+            minute in 0u32..60,
+            second in 0u32..60,
+        ) {
+            let due = format!(
+                "{:04}{:02}{:02}T{:02}{:02}{:02}Z",
+                year, month, day, hour, minute, second
+            );
+            let parsed = parse_due_date(&due).unwrap().with_timezone(&chrono::Utc);
+            prop_assert_eq!(parsed.format("%Y%m%dT%H%M%SZ").to_string(), due);
+        }
+    }
+
+    #[test]
+    fn test_generate_valid_waybar_output() {
+        let waybar_output = generate_waybar_output(
+            &[
+                Task {
+                    id: 1,
+                    description: Some("Test1".to_string()),
+                    priority: Some("H".to_string()),
+                    due: Some("20241206T143002Z".to_string()),
+                    urgency: Some(42.0),
+                    ..Task::default()
+                },
+                Task {
+                    id: 2,
+                    description: Some("Test2".to_string()),
+                    priority: Some("M".to_string()),
+                    due: Some("20241206T173002Z".to_string()),
+                    urgency: Some(5.0),
+                    ..Task::default()
+                },
+            ],
+            &Config::default(),
+        );
 
         let expected_due1 =
             chrono::NaiveDateTime::parse_from_str("20241206T143002Z", "%Y%m%dT%H%M%SZ")
@@ -209,24 +1711,1774 @@ pub mod tests {
             waybar_output,
             WaybarOutput {
                 text: expected_text,
-                tooltip: expected_tooltip,
+                tooltip: Some(expected_tooltip),
+                class: Some("urgency-high".to_string()),
+                percentage: None,
+                alt: None,
             }
         );
     }
 
     #[test]
     fn test_generate_empty_tasks_waybar_output() {
-        let waybar_output = generate_waybar_output(&[]);
+        let waybar_output = generate_waybar_output(&[], &Config::default());
 
         assert_eq!(
             waybar_output,
             WaybarOutput {
                 text: "No tasks.".to_string(),
-                tooltip: "No tasks.".to_string()
+                tooltip: Some("No tasks.".to_string()),
+                class: None,
+                percentage: None,
+                alt: None,
             }
         );
     }
 
+    #[test]
+    fn test_multiline_description_stays_on_one_line() {
+        let task = Task {
+            id: 1,
+            description: Some("First line\nsecond line\r\nthird line".to_string()),
+            priority: None,
+            due: None,
+            urgency: None,
+            ..Task::default()
+        };
+
+        assert_eq!(
+            task.construct_task_output(&Config::default(), Line::Tooltip, Local::now()),
+            "1 First line second line third line"
+        );
+
+        let config = Config {
+            newline_placeholder: " / ".to_string(),
+            ..Config::default()
+        };
+        assert_eq!(
+            task.construct_task_output(&config, Line::Tooltip, Local::now()),
+            "1 First line / second line / third line"
+        );
+    }
+
+    #[test]
+    fn test_scaled_urgency_display() {
+        let task = Task {
+            id: 1,
+            description: Some("Test".to_string()),
+            priority: None,
+            due: None,
+            urgency: Some(42.0),
+            ..Task::default()
+        };
+        let config = Config {
+            urgency_scale: Some(60.0),
+            ..Config::default()
+        };
+
+        assert_eq!(
+            task.construct_task_output(&config, Line::Tooltip, Local::now()),
+            "1 Test, Urgency: 7"
+        );
+        assert_eq!(scale_urgency(-3.0, 60.0), 0);
+        assert_eq!(scale_urgency(120.0, 60.0), 10);
+    }
+
+    fn simple_task(id: u32, description: &str) -> Task {
+        Task {
+            id,
+            description: Some(description.to_string()),
+            ..Task::default()
+        }
+    }
+
+    #[test]
+    fn test_tooltip_skip_top_with_multiple_tasks() {
+        let config = Config {
+            tooltip_skip_top: true,
+            ..Config::default()
+        };
+        let waybar_output = generate_waybar_output(
+            &[
+                simple_task(1, "First"),
+                simple_task(2, "Second"),
+                simple_task(3, "Third"),
+            ],
+            &config,
+        );
+
+        assert_eq!(waybar_output.text, "1 First");
+        assert_eq!(waybar_output.tooltip.as_deref(), Some("2 Second\n3 Third"));
+    }
+
+    #[test]
+    fn test_tooltip_skip_top_with_single_task() {
+        let config = Config {
+            tooltip_skip_top: true,
+            ..Config::default()
+        };
+        let waybar_output = generate_waybar_output(&[simple_task(1, "Only")], &config);
+
+        assert_eq!(waybar_output.text, "1 Only");
+        assert_eq!(waybar_output.tooltip.as_deref(), Some("1 Only"));
+    }
+
+    #[test]
+    fn test_recurrence_marker() {
+        let task = Task {
+            recur: Some("weekly".to_string()),
+            ..simple_task(1, "Water plants")
+        };
+        let config = Config::default();
+
+        assert_eq!(
+            task.construct_task_output(&config, Line::Tooltip, Local::now()),
+            "1 Water plants 🔁"
+        );
+        assert_eq!(
+            task.construct_task_output(&config, Line::Text, Local::now()),
+            "1 Water plants"
+        );
+
+        let config = Config {
+            recurrence_marker_in_text: true,
+            ..Config::default()
+        };
+        assert_eq!(
+            task.construct_task_output(&config, Line::Text, Local::now()),
+            "1 Water plants 🔁"
+        );
+    }
+
+    #[test]
+    fn test_sort_tasks_by_uuid_on_duplicate_id() {
+        let mut tasks = vec![
+            Task {
+                uuid: Some("b0000000-0000-0000-0000-000000000000".to_string()),
+                ..simple_task(1, "Second")
+            },
+            Task {
+                uuid: Some("a0000000-0000-0000-0000-000000000000".to_string()),
+                ..simple_task(1, "First")
+            },
+        ];
+
+        sort_tasks(&mut tasks);
+
+        let descriptions: Vec<_> = tasks.iter().map(|t| t.description.as_deref()).collect();
+        assert_eq!(descriptions, [Some("First"), Some("Second")]);
+    }
+
+    #[test]
+    fn test_tooltip_unchanged_for_many_tasks() {
+        let tasks: Vec<Task> = (1..=1000)
+            .map(|id| Task {
+                id,
+                description: Some(format!("Task number {}", id)),
+                priority: ["H", "M", "L"].get(id as usize % 4).map(|p| p.to_string()),
+                due: (id % 2 == 0).then(|| "20241206T143002Z".to_string()),
+                urgency: Some(f64::from(id) / 7.0),
+                ..Task::default()
+            })
+            .collect();
+        let config = Config::default();
+
+        let joined_tooltip = tasks
+            .iter()
+            .map(|task| task.construct_task_output(&config, Line::Tooltip, Local::now()))
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        assert_eq!(
+            generate_waybar_output(&tasks, &config).tooltip,
+            Some(joined_tooltip)
+        );
+    }
+
+    #[test]
+    fn test_sort_tasks_matches_per_comparison_parsing() {
+        let mut tasks: Vec<Task> = (1..=200)
+            .map(|id| Task {
+                id,
+                due: (id % 3 != 0).then(|| format!("202412{:02}T143002Z", id % 28 + 1)),
+                urgency: (id % 5 != 0).then(|| f64::from(id % 7)),
+                ..Task::default()
+            })
+            .collect();
+
+        let mut expected = tasks.clone();
+        expected.sort_by(|a, b| {
+            let parse = |t: &Task| t.due.as_deref().and_then(|d| parse_due_date(d).ok());
+            b.urgency
+                .partial_cmp(&a.urgency)
+                .unwrap_or(std::cmp::Ordering::Equal)
+                .then_with(|| parse(a).cmp(&parse(b)))
+                .then_with(|| a.id.cmp(&b.id))
+        });
+
+        sort_tasks(&mut tasks);
+        assert_eq!(tasks, expected);
+    }
+
+    #[test]
+    fn test_class_combines_urgency_and_active() {
+        let tasks = [
+            Task {
+                urgency: Some(15.0),
+                ..simple_task(1, "Urgent")
+            },
+            Task {
+                urgency: Some(1.0),
+                start: Some("20241206T143002Z".to_string()),
+                ..simple_task(2, "Started")
+            },
+        ];
+        let waybar_output = generate_waybar_output(&tasks, &Config::default());
+        assert_eq!(waybar_output.class.as_deref(), Some("urgency-high active"));
+
+        assert_eq!(
+            serde_json::to_value(&waybar_output).unwrap()["class"],
+            serde_json::json!(["urgency-high", "active"])
+        );
+
+        let waybar_output = generate_waybar_output(&tasks[..1], &Config::default());
+        assert_eq!(waybar_output.class.as_deref(), Some("urgency-high"));
+    }
+
+    #[test]
+    fn test_urgency_class_thresholds() {
+        let config = Config::default();
+        assert_eq!(
+            urgency_class(config.high_urgency_threshold, &config),
+            "urgency-high"
+        );
+        assert_eq!(
+            urgency_class(config.medium_urgency_threshold, &config),
+            "urgency-medium"
+        );
+        assert_eq!(urgency_class(0.0, &config), "urgency-low");
+    }
+
+    #[test]
+    fn test_missing_description_placeholder() {
+        let task = Task {
+            id: 3,
+            urgency: Some(1.0),
+            ..Task::default()
+        };
+
+        assert_eq!(
+            task.construct_task_output(&Config::default(), Line::Tooltip, Local::now()),
+            "3 (no description), Urgency: 1.00"
+        );
+
+        let config = Config {
+            missing_description_placeholder: String::new(),
+            ..Config::default()
+        };
+        assert_eq!(
+            task.construct_task_output(&config, Line::Tooltip, Local::now()),
+            "3 Urgency: 1.00"
+        );
+    }
+
+    #[test]
+    fn test_write_extra_outputs() {
+        let dir = tempfile::tempdir().unwrap();
+        let list_path = dir.path().join("waybar-tasks.json");
+        let count_path = dir.path().join("waybar-task-count.json");
+        let config = Config {
+            extra_outputs: vec![ExtraOutput {
+                output: count_path.clone(),
+                text_mode: TextMode::Count,
+            }],
+            ..Config::default()
+        };
+        let tasks = [simple_task(1, "Buy milk"), simple_task(2, "Call bank")];
+
+        FileSink::new(list_path.clone(), false)
+            .write(&generate_waybar_output(&tasks, &config))
+            .unwrap();
+        write_extra_outputs(&tasks, &[], &config).unwrap();
+
+        let text = |path: &Path| {
+            let json: serde_json::Value =
+                serde_json::from_str(&std::fs::read_to_string(path).unwrap()).unwrap();
+            json["text"].as_str().unwrap().to_string()
+        };
+        assert_eq!(text(&list_path), "1 Buy milk");
+        assert_eq!(text(&count_path), "2 tasks");
+    }
+
+    fn local_datetime(year: i32, month: u32, day: u32, hour: u32, minute: u32) -> DateTime<Local> {
+        Local
+            .with_ymd_and_hms(year, month, day, hour, minute, 0)
+            .unwrap()
+    }
+
+    /// Formats a local time the way taskwarrior exports dates.
+    fn task_date(datetime: DateTime<Local>) -> String {
+        datetime
+            .with_timezone(&chrono::Utc)
+            .format("%Y%m%dT%H%M%SZ")
+            .to_string()
+    }
+
+    fn task_due_at(datetime: DateTime<Local>) -> Task {
+        Task {
+            due: Some(task_date(datetime)),
+            ..simple_task(1, "Due")
+        }
+    }
+
+    #[test]
+    fn test_is_due_today_around_midnight() {
+        let now = local_datetime(2024, 12, 6, 12, 0);
+
+        assert!(task_due_at(local_datetime(2024, 12, 6, 0, 0)).is_due_today(now));
+        assert!(task_due_at(local_datetime(2024, 12, 6, 23, 59)).is_due_today(now));
+        assert!(!task_due_at(local_datetime(2024, 12, 7, 0, 1)).is_due_today(now));
+        assert!(!task_due_at(local_datetime(2024, 12, 5, 23, 59)).is_due_today(now));
+        assert!(!simple_task(1, "No due").is_due_today(now));
+
+        let just_before_midnight = local_datetime(2024, 12, 6, 23, 59);
+        assert!(!task_due_at(local_datetime(2024, 12, 7, 0, 1)).is_due_today(just_before_midnight));
+    }
+
+    #[test]
+    fn test_due_today_class_and_alt() {
+        let now = local_datetime(2024, 12, 6, 12, 0);
+        let tasks = [task_due_at(local_datetime(2024, 12, 6, 23, 59))];
+
+        let waybar_output = generate_waybar_output_at(&tasks, &[], &Config::default(), now);
+        assert_eq!(
+            waybar_output.class.as_deref(),
+            Some("urgency-low due-today")
+        );
+        assert_eq!(waybar_output.alt.as_deref(), Some("due-today"));
+
+        let tomorrow = local_datetime(2024, 12, 7, 0, 1);
+        let waybar_output = generate_waybar_output_at(&tasks, &[], &Config::default(), tomorrow);
+        assert_eq!(waybar_output.class.as_deref(), Some("urgency-low"));
+        assert_eq!(waybar_output.alt, None);
+    }
+
+    #[test]
+    fn test_priority_alt() {
+        let config = Config {
+            alt_source: AltSource::Priority,
+            ..Config::default()
+        };
+        for (priority, alt) in [
+            (Some("H"), "high"),
+            (Some("M"), "medium"),
+            (Some("L"), "low"),
+            (None, "none"),
+        ] {
+            let tasks = [Task {
+                priority: priority.map(String::from),
+                ..simple_task(1, "Task")
+            }];
+            assert_eq!(
+                generate_waybar_output(&tasks, &config).alt.as_deref(),
+                Some(alt)
+            );
+        }
+    }
+
+    #[test]
+    fn test_pango_markup_colors_lines_by_urgency() {
+        let config = Config {
+            pango_markup: true,
+            palette: crate::pango::Palette::parse(r##"urgency-high = "#ff0000""##),
+            ..Config::default()
+        };
+        let urgent = Task {
+            urgency: Some(12.0),
+            ..simple_task(1, "Pay <rent> & bills")
+        };
+        let relaxed = Task {
+            urgency: Some(0.5),
+            ..simple_task(2, "Relax")
+        };
+
+        let waybar_output = generate_waybar_output(&[urgent, relaxed], &config);
+        assert_eq!(
+            waybar_output.text,
+            "<span foreground=\"#ff0000\">1 Pay &lt;rent&gt; &amp; bills, Urgency: 12.00</span>"
+        );
+        assert_eq!(
+            waybar_output.tooltip.as_deref().unwrap(),
+            "<span foreground=\"#ff0000\">1 Pay &lt;rent&gt; &amp; bills, Urgency: 12.00</span>\n\
+             2 Relax, Urgency: 0.50"
+        );
+    }
+
+    #[test]
+    fn test_taskwarrior_colors() {
+        let config = Config {
+            pango_markup: true,
+            taskwarrior_colors: true,
+            task_colors: TaskColors::parse("color.tag.home=rgb040\ncolor.uda.priority.H=red\n"),
+            ..Config::default()
+        };
+        let home = Task {
+            tags: vec!["home".to_string()],
+            priority: Some("H".to_string()),
+            ..simple_task(1, "Water plants")
+        };
+        let line = home.construct_task_output(&config, Line::Text, Local::now());
+        assert_eq!(
+            line,
+            "<span foreground=\"#00d700\">1 Water plants, Prio: H</span>"
+        );
+
+        let other = Task {
+            urgency: Some(15.0),
+            ..simple_task(2, "Urgent")
+        };
+        let line = other.construct_task_output(&config, Line::Text, Local::now());
+        assert!(
+            line.starts_with("<span foreground=\"#f38ba8\">"),
+            "{}",
+            line
+        );
+    }
+
+    #[test]
+    fn test_tag_colors() {
+        let task = Task {
+            tags: vec!["urgent".to_string(), "home".to_string()],
+            ..simple_task(1, "Fix sink")
+        };
+        let config = Config::parse(
+            r##"
+            show_tags = true
+            pango_markup = true
+            tag_colors = { "+urgent" = "#ff0000" }
+            "##,
+        )
+        .unwrap();
+
+        assert_eq!(
+            task.construct_task_output(&config, Line::Tooltip, Local::now()),
+            "1 Fix sink, <span foreground=\"#ff0000\">+urgent</span> +home"
+        );
+
+        let config = Config {
+            pango_markup: false,
+            ..config
+        };
+        assert_eq!(
+            task.construct_task_output(&config, Line::Tooltip, Local::now()),
+            "1 Fix sink, +urgent +home"
+        );
+    }
+
+    #[test]
+    fn test_max_description_lengths() {
+        let tasks = [
+            simple_task(1, "A rather long description for the bar"),
+            simple_task(2, "Another long one in the tooltip"),
+            simple_task(3, "Short"),
+        ];
+        let config = Config {
+            max_text_len: Some(20),
+            max_tooltip_desc_len: Some(8),
+            ..Config::default()
+        };
+
+        let waybar_output = generate_waybar_output(&tasks, &config);
+        assert_eq!(waybar_output.text, "1 A rather long descr…");
+        assert_eq!(
+            waybar_output.tooltip.as_deref(),
+            Some("1 A rathe…\n2 Another…\n3 Short")
+        );
+    }
+
+    #[test]
+    fn test_sort_tasks_by_newest_and_oldest() {
+        let created = |id: u32, entry: &str| Task {
+            entry: Some(entry.to_string()),
+            urgency: Some(f64::from(id)),
+            ..simple_task(id, "Task")
+        };
+        let tasks = vec![
+            created(1, "20241201T080000Z"),
+            created(2, "20241203T080000Z"),
+            created(3, "20241202T080000Z"),
+            Task {
+                modified: Some("20241204T080000Z".to_string()),
+                ..simple_task(4, "Only modified")
+            },
+            simple_task(5, "No dates"),
+        ];
+
+        let ids = |key: SortKey| {
+            let mut tasks = tasks.clone();
+            sort_tasks_by(&mut tasks, key);
+            tasks.iter().map(|t| t.id).collect::<Vec<_>>()
+        };
+
+        assert_eq!(ids(SortKey::Newest), [4, 2, 3, 1, 5]);
+        assert_eq!(ids(SortKey::Oldest), [1, 3, 2, 4, 5]);
+        assert_eq!(ids(SortKey::Urgency), [3, 2, 1, 4, 5]);
+    }
+
+    #[test]
+    fn test_format_due_localized() {
+        let due = local_datetime(2024, 12, 3, 14, 30);
+        let german = Config {
+            locale: Some("de_DE".to_string()),
+            due_format: "%A, %d. %B %H:%M".to_string(),
+            ..Config::default()
+        };
+        assert_eq!(format_due(due, &german), "Dienstag, 03. Dezember 14:30");
+
+        let unknown = Config {
+            locale: Some("xx_XX".to_string()),
+            ..german
+        };
+        assert_eq!(format_due(due, &unknown), "Tuesday, 03. December 14:30");
+        assert_eq!(format_due(due, &Config::default()), "Tue, 24-12-03 14:30");
+
+        let invalid = Config {
+            due_format: "%Q".to_string(),
+            ..Config::default()
+        };
+        assert_eq!(format_due(due, &invalid), "Tue, 24-12-03 14:30");
+    }
+
+    #[test]
+    fn test_diff_tasks() {
+        let snapshot = |tasks: &[(u32, f64)]| -> Vec<TaskSnapshot> {
+            tasks
+                .iter()
+                .map(|&(id, urgency)| TaskSnapshot { id, urgency })
+                .collect()
+        };
+        let old = snapshot(&[(1, 9.0), (2, 8.0), (3, 7.0), (4, 1.0)]);
+
+        assert_eq!(diff_tasks(&old, &old), TaskDiff::default());
+        assert_eq!(diff_tasks(&old, &old).to_string(), "no changes");
+
+        let new = snapshot(&[(5, 12.0), (3, 10.0), (1, 9.0), (2, 8.0), (6, 0.5)]);
+        let diff = diff_tasks(&old, &new);
+        assert_eq!(
+            diff,
+            TaskDiff {
+                added: 2,
+                removed: 1,
+                reordered: 3,
+            }
+        );
+        assert_eq!(diff.to_string(), "+2 tasks, -1 task, 3 reordered");
+
+        // Same order, but a changed urgency still counts.
+        let new = snapshot(&[(1, 9.0), (2, 8.5), (3, 7.0), (4, 1.0)]);
+        assert_eq!(diff_tasks(&old, &new).reordered, 1);
+    }
+
+    #[test]
+    fn test_field_labels() {
+        let task = Task {
+            priority: Some("H".to_string()),
+            due: Some(task_date(local_datetime(2024, 12, 3, 14, 30))),
+            urgency: Some(12.0),
+            ..simple_task(1, "Task")
+        };
+        let config = Config {
+            urgency_label: "U".to_string(),
+            priority_label: String::new(),
+            due_label: "Fällig".to_string(),
+            ..Config::default()
+        };
+        assert_eq!(
+            task.construct_task_output(&config, Line::Tooltip, Local::now()),
+            "1 Task, H, Fällig: Tue, 24-12-03 14:30, U: 12.00"
+        );
+    }
+
+    #[test]
+    fn test_priority_style_bang() {
+        assert_eq!(format_priority("H", PriorityStyle::Bang), "!!!");
+        assert_eq!(format_priority("M", PriorityStyle::Bang), "!!");
+        assert_eq!(format_priority("L", PriorityStyle::Bang), "!");
+        assert_eq!(format_priority("H", PriorityStyle::Label), "H");
+
+        let config = Config {
+            priority_style: PriorityStyle::Bang,
+            field_order: vec![Field::Description, Field::Priority],
+            ..Config::default()
+        };
+        let now = Local::now();
+        let task = Task {
+            priority: Some("M".to_string()),
+            ..simple_task(1, "Task")
+        };
+        assert_eq!(
+            task.construct_task_output(&config, Line::Text, now),
+            "1 Task, !!"
+        );
+        assert_eq!(
+            simple_task(2, "Task").construct_task_output(&config, Line::Text, now),
+            "2 Task"
+        );
+    }
+
+    #[test]
+    fn test_date_preference() {
+        let task = Task {
+            due: Some(task_date(local_datetime(2024, 12, 10, 12, 0))),
+            scheduled: Some(task_date(local_datetime(2024, 12, 5, 9, 0))),
+            ..simple_task(1, "Task")
+        };
+        let render = |date_preference, task: &Task| {
+            let config = Config {
+                date_preference,
+                field_order: vec![Field::Description, Field::Due],
+                ..Config::default()
+            };
+            task.construct_task_output(&config, Line::Tooltip, Local::now())
+        };
+
+        assert_eq!(
+            render(DatePreference::Due, &task),
+            "1 Task, Due: Tue, 24-12-10 12:00"
+        );
+        assert_eq!(
+            render(DatePreference::Scheduled, &task),
+            "1 Task, Scheduled: Thu, 24-12-05 09:00"
+        );
+        assert_eq!(
+            render(DatePreference::Earliest, &task),
+            "1 Task, Scheduled: Thu, 24-12-05 09:00"
+        );
+
+        let due_first = Task {
+            scheduled: Some(task_date(local_datetime(2024, 12, 12, 9, 0))),
+            ..task.clone()
+        };
+        assert_eq!(
+            render(DatePreference::Earliest, &due_first),
+            "1 Task, Due: Tue, 24-12-10 12:00"
+        );
+    }
+
+    #[test]
+    fn test_no_due_marker() {
+        let tasks = vec![
+            Task {
+                urgency: Some(2.0),
+                ..simple_task(1, "Read book")
+            },
+            Task {
+                due: Some(task_date(local_datetime(2024, 12, 3, 14, 30))),
+                ..simple_task(2, "Pay rent")
+            },
+        ];
+        let config = Config {
+            field_order: vec![Field::Description, Field::Due],
+            no_due_marker: "(no due)".to_string(),
+            ..Config::default()
+        };
+        let output = generate_waybar_output(&tasks, &config);
+        assert_eq!(output.text, "1 Read book");
+        assert_eq!(
+            output.tooltip.as_deref(),
+            Some("1 Read book, (no due)\n2 Pay rent, Due: Tue, 24-12-03 14:30")
+        );
+    }
+
+    #[test]
+    fn test_relative_due() {
+        let now = local_datetime(2024, 12, 3, 12, 0);
+        let task = task_due_at(local_datetime(2024, 12, 3, 13, 59));
+        let render = |task: &Task, relative_granularity| {
+            let config = Config {
+                field_order: vec![Field::Due],
+                relative_due: true,
+                relative_granularity,
+                ..Config::default()
+            };
+            task.construct_task_output(&config, Line::Text, now)
+        };
+        assert_eq!(render(&task, RelativeGranularity::Fine), "1 Due in 1h 59m");
+        assert_eq!(render(&task, RelativeGranularity::Coarse), "1 Due in 2h");
+
+        let overdue = task_due_at(local_datetime(2024, 11, 30, 2, 0));
+        assert_eq!(
+            render(&overdue, RelativeGranularity::Fine),
+            "1 Due 3d 10h ago"
+        );
+        assert_eq!(
+            render(&overdue, RelativeGranularity::Coarse),
+            "1 Due 3d ago"
+        );
+
+        let seconds = chrono::TimeDelta::seconds;
+        assert_eq!(
+            humanize_delta(seconds(20), RelativeGranularity::Fine),
+            "now"
+        );
+        assert_eq!(
+            humanize_delta(seconds(20), RelativeGranularity::Coarse),
+            "now"
+        );
+        assert_eq!(
+            humanize_delta(seconds(3570), RelativeGranularity::Coarse),
+            "in 1h"
+        );
+        assert_eq!(
+            humanize_delta(seconds(90_000), RelativeGranularity::Fine),
+            "in 1d 1h"
+        );
+        assert_eq!(
+            humanize_delta(seconds(86_460), RelativeGranularity::Fine),
+            "in 1d"
+        );
+    }
+
+    #[test]
+    fn test_smart_due() {
+        // A Tuesday.
+        let now = local_datetime(2024, 12, 3, 9, 0);
+        let config = Config {
+            smart_due: true,
+            field_order: vec![Field::Description, Field::Due],
+            ..Config::default()
+        };
+        let render = |due: DateTime<Local>| {
+            Task {
+                due: Some(task_date(due)),
+                ..simple_task(1, "Task")
+            }
+            .construct_task_output(&config, Line::Tooltip, now)
+        };
+
+        assert_eq!(
+            render(local_datetime(2024, 12, 3, 14, 30)),
+            "1 Task, Due today 14:30"
+        );
+        assert_eq!(
+            render(local_datetime(2024, 12, 4, 8, 0)),
+            "1 Task, Due tomorrow 08:00"
+        );
+        assert_eq!(
+            render(local_datetime(2024, 12, 7, 18, 15)),
+            "1 Task, Due Saturday 18:15"
+        );
+        assert_eq!(
+            render(local_datetime(2024, 12, 24, 12, 0)),
+            "1 Task, Due: Tue, 24-12-24 12:00"
+        );
+        // Overdue tasks keep the date.
+        assert_eq!(
+            render(local_datetime(2024, 12, 2, 12, 0)),
+            "1 Task, Due: Mon, 24-12-02 12:00"
+        );
+    }
+
+    #[test]
+    fn test_class_hysteresis() {
+        let mut config = Config {
+            class_hysteresis: 0.5,
+            previous_urgency_class: Some("urgency-low".to_string()),
+            ..Config::default()
+        };
+        let mut classes = Vec::new();
+        for urgency in [4.9, 5.1, 4.8, 5.4, 5.6, 5.2, 4.7, 5.3, 4.4, 4.6] {
+            let tasks = [Task {
+                urgency: Some(urgency),
+                ..simple_task(1, "Task")
+            }];
+            let class = generate_waybar_output(&tasks, &config).class.unwrap();
+            config.previous_urgency_class = Some(class.clone());
+            classes.push(class);
+        }
+        assert_eq!(
+            classes,
+            [
+                "urgency-low",
+                "urgency-low",
+                "urgency-low",
+                "urgency-low",
+                "urgency-medium",
+                "urgency-medium",
+                "urgency-medium",
+                "urgency-medium",
+                "urgency-low",
+                "urgency-low"
+            ]
+        );
+
+        // Large jumps still go straight through.
+        let config = Config {
+            class_hysteresis: 0.5,
+            previous_urgency_class: Some("urgency-high".to_string()),
+            ..Config::default()
+        };
+        assert_eq!(
+            urgency_class_with_hysteresis(1.0, "urgency-high", &config),
+            "urgency-low"
+        );
+        assert_eq!(
+            urgency_class_with_hysteresis(9.7, "urgency-high", &config),
+            "urgency-high"
+        );
+        assert_eq!(
+            urgency_class_with_hysteresis(9.4, "urgency-high", &config),
+            "urgency-medium"
+        );
+    }
+
+    #[test]
+    fn test_read_previous_urgency_class() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("waybar-tasks.json");
+        assert_eq!(read_previous_urgency_class(&path), None);
+
+        let tasks = [Task {
+            urgency: Some(7.0),
+            start: Some("20241206T100000Z".to_string()),
+            ..simple_task(1, "Task")
+        }];
+        let output = generate_waybar_output(&tasks, &Config::default());
+        write_waybar_json(&output, &path, false).unwrap();
+        assert_eq!(
+            read_previous_urgency_class(&path).as_deref(),
+            Some("urgency-medium")
+        );
+    }
+
+    #[test]
+    fn test_tooltip_json() {
+        let tasks = [
+            Task {
+                priority: Some("H".to_string()),
+                urgency: Some(4.5),
+                tags: vec!["home".to_string()],
+                ..simple_task(1, "Fix \"the\" <door>\nnow")
+            },
+            simple_task(2, "Second"),
+        ];
+        let config = Config {
+            tooltip_format: TooltipFormat::Json,
+            show_tags: true,
+            pango_markup: true,
+            ..Config::default()
+        };
+
+        let output = generate_waybar_output(&tasks, &config);
+        let tooltip: serde_json::Value =
+            serde_json::from_str(output.tooltip.as_deref().unwrap()).unwrap();
+        assert_eq!(
+            tooltip,
+            serde_json::json!([
+                {
+                    "id": 1,
+                    "description": "Fix \"the\" <door> now",
+                    "tags": "+home",
+                    "priority": "H",
+                    "urgency": "4.50"
+                },
+                {"id": 2, "description": "Second"}
+            ])
+        );
+
+        let output = generate_waybar_output(&[], &config);
+        assert_eq!(output.tooltip.as_deref(), Some("[]"));
+
+        // The tooltip survives being embedded in the output json.
+        let output = generate_waybar_output(&tasks, &config);
+        let serialized = serde_json::to_string(&output).unwrap();
+        let parsed: serde_json::Value = serde_json::from_str(&serialized).unwrap();
+        assert_eq!(parsed["tooltip"].as_str(), output.tooltip.as_deref());
+    }
+
+    #[test]
+    fn test_status_prefix() {
+        let config = Config {
+            status_prefix: true,
+            ..Config::default()
+        };
+        let task = |id, status: &str| Task {
+            status: Some(status.to_string()),
+            ..simple_task(id, "Task")
+        };
+        let tasks = [
+            task(1, "pending"),
+            task(2, "waiting"),
+            task(3, "completed"),
+            task(4, "deleted"),
+        ];
+        assert_eq!(
+            generate_waybar_output(&tasks, &config).tooltip.unwrap(),
+            "1 Task\n⏸ 2 Task\n✓ 3 Task\n✗ 4 Task"
+        );
+        assert_eq!(generate_waybar_output(&tasks, &config).text, "1 Task");
+        assert_eq!(
+            generate_waybar_output(&tasks, &Config::default())
+                .tooltip
+                .unwrap(),
+            "1 Task\n2 Task\n3 Task\n4 Task"
+        );
+    }
+
+    #[test]
+    fn test_annotation_count_marker() {
+        let tasks: Vec<Task> = serde_json::from_str(
+            r#"[{"id": 1, "description": "Plan trip", "urgency": 2.0, "annotations": [
+                {"entry": "20241201T100000Z", "description": "Book hotel"},
+                {"entry": "20241202T100000Z", "description": "Ask Sam"}
+            ]}]"#,
+        )
+        .unwrap();
+        let config = Config {
+            annotation_count_marker: true,
+            ..Config::default()
+        };
+        assert_eq!(
+            tasks[0].construct_task_output(&config, Line::Tooltip, Local::now()),
+            "1 Plan trip, Urgency: 2.00 📎2"
+        );
+        assert_eq!(
+            simple_task(2, "Plain").construct_task_output(&config, Line::Tooltip, Local::now()),
+            "2 Plain"
+        );
+        assert_eq!(
+            tasks[0].construct_task_output(&Config::default(), Line::Tooltip, Local::now()),
+            "1 Plan trip, Urgency: 2.00"
+        );
+    }
+
+    #[test]
+    fn test_due_gradient() {
+        let now = local_datetime(2024, 12, 6, 12, 0);
+        let config = Config {
+            pango_markup: true,
+            due_gradient: true,
+            due_gradient_days: 10.0,
+            ..Config::default()
+        };
+        let color_at = |due| {
+            let line = task_due_at(due).construct_task_output(&config, Line::Tooltip, now);
+            line[..=line.find('>').unwrap()].to_string()
+        };
+
+        assert_eq!(
+            color_at(local_datetime(2024, 12, 5, 12, 0)),
+            "<span foreground=\"#f38ba8\">"
+        );
+        assert_eq!(
+            color_at(local_datetime(2024, 12, 11, 12, 0)),
+            "<span foreground=\"#cdb7a5\">"
+        );
+        assert_eq!(
+            color_at(local_datetime(2025, 1, 30, 12, 0)),
+            "<span foreground=\"#a6e3a1\">"
+        );
+
+        // The bar text keeps the urgency colors.
+        let text = task_due_at(local_datetime(2024, 12, 5, 12, 0)).construct_task_output(
+            &config,
+            Line::Text,
+            now,
+        );
+        assert!(!text.contains("<span"), "{}", text);
+    }
+
+    #[test]
+    fn test_overdue_source() {
+        let now = local_datetime(2024, 12, 3, 12, 0);
+        let tagged = Task {
+            tags: vec!["OVERDUE".to_string()],
+            ..task_due_at(local_datetime(2024, 12, 20, 9, 0))
+        };
+        let past_due = task_due_at(local_datetime(2024, 12, 1, 9, 0));
+
+        // The tag takes precedence over the due date, which only counts without it.
+        assert!(!tagged.is_overdue(OverdueSource::Computed, now));
+        assert!(tagged.is_overdue(OverdueSource::Tag, now));
+        assert!(past_due.is_overdue(OverdueSource::Computed, now));
+        assert!(past_due.is_overdue(OverdueSource::Tag, now));
+
+        let config = Config {
+            pango_markup: true,
+            due_gradient: true,
+            overdue_source: OverdueSource::Tag,
+            ..Config::default()
+        };
+        let line = tagged.construct_task_output(&config, Line::Tooltip, now);
+        assert!(
+            line.starts_with("<span foreground=\"#f38ba8\">"),
+            "{}",
+            line
+        );
+    }
+
+    #[test]
+    fn test_waiting_section() {
+        let tasks = [simple_task(1, "Pending"), simple_task(2, "Also pending")];
+        let waiting = [simple_task(3, "Waiting")];
+        let config = Config {
+            tag_summary: true,
+            ..Config::default()
+        };
+
+        let output = generate_waybar_output_with_waiting(&tasks, &waiting, &config);
+        assert_eq!(output.text, "1 Pending");
+        assert_eq!(
+            output.tooltip.as_deref(),
+            Some("1 Pending\n2 Also pending\n── Waiting ──\n3 Waiting")
+        );
+
+        let output = generate_waybar_output_with_waiting(&[], &waiting, &config);
+        assert_eq!(output.text, "No tasks.");
+        assert_eq!(
+            output.tooltip.as_deref(),
+            Some("No tasks.\n── Waiting ──\n3 Waiting")
+        );
+
+        assert_eq!(
+            generate_waybar_output_with_waiting(&tasks, &[], &config),
+            generate_waybar_output(&tasks, &config)
+        );
+    }
+
+    #[test]
+    fn test_max_tasks_keeps_true_count() {
+        let tasks: Vec<Task> = (1..=100).map(|id| simple_task(id, "Task")).collect();
+        let config = Config {
+            max_tasks: Some(10),
+            show_count: true,
+            ..Config::default()
+        };
+
+        let output = generate_waybar_output(&tasks, &config);
+        assert_eq!(output.text, "1 Task (100 tasks)");
+        let tooltip = output.tooltip.unwrap();
+        assert_eq!(tooltip.lines().count(), 10);
+        assert_eq!(tooltip.lines().last(), Some("10 Task"));
+
+        let output = generate_waybar_output(&tasks[..1], &config);
+        assert_eq!(output.text, "1 Task (1 task)");
+    }
+
+    #[test]
+    fn test_tooltip_sort() {
+        let due = |day| Some(task_date(local_datetime(2024, 12, day, 9, 0)));
+        let tasks = vec![
+            Task {
+                urgency: Some(9.0),
+                due: due(20),
+                ..simple_task(1, "Tax return")
+            },
+            Task {
+                urgency: Some(6.0),
+                priority: Some("H".to_string()),
+                ..simple_task(2, "Read book")
+            },
+            Task {
+                urgency: Some(3.0),
+                due: due(4),
+                priority: Some("L".to_string()),
+                ..simple_task(3, "Pay rent")
+            },
+        ];
+        let config = Config {
+            field_order: vec![Field::Description],
+            tooltip_sort: Some(TooltipSort::Due),
+            ..Config::default()
+        };
+
+        let output = generate_waybar_output(&tasks, &config);
+        assert_eq!(output.text, "1 Tax return");
+        assert_eq!(
+            output.tooltip.as_deref(),
+            Some("3 Pay rent\n1 Tax return\n2 Read book")
+        );
+
+        let config = Config {
+            tooltip_sort: Some(TooltipSort::Priority),
+            ..config
+        };
+        let output = generate_waybar_output(&tasks, &config);
+        assert_eq!(
+            output.tooltip.as_deref(),
+            Some("2 Read book\n3 Pay rent\n1 Tax return")
+        );
+    }
+
+    #[test]
+    fn test_whitespace_in_descriptions() {
+        let task = simple_task(1, "  spaced  out  ");
+        let render = |trim_descriptions, collapse_whitespace| {
+            let config = Config {
+                trim_descriptions,
+                collapse_whitespace,
+                ..Config::default()
+            };
+            task.construct_task_output(&config, Line::Text, Local::now())
+        };
+        assert_eq!(render(false, false), "1   spaced  out  ");
+        assert_eq!(render(true, false), "1 spaced  out");
+        assert_eq!(render(false, true), "1 spaced out");
+    }
+
+    #[test]
+    fn test_escape_placeholders() {
+        let tasks = vec![simple_task(1, "50% {done}"), simple_task(2, "Rest")];
+        let config = Config {
+            escape_placeholders: true,
+            show_next: true,
+            ..Config::default()
+        };
+        let output = generate_waybar_output(&tasks, &config);
+        assert_eq!(output.text, "1 50%% {{done}} → (next: Rest)");
+        assert_eq!(output.tooltip.as_deref(), Some("1 50%% {{done}}\n2 Rest"));
+
+        let output = generate_waybar_output(&tasks[..1], &Config::default());
+        assert_eq!(output.text, "1 50% {done}");
+    }
+
+    #[test]
+    fn test_id_display() {
+        let task = Task {
+            uuid: Some("5e0f9a3c-7b1d-4c2e-9f8a-0d6b3e2a1c4f".to_string()),
+            ..simple_task(12, "Pay rent")
+        };
+        let render = |id_display| {
+            let config = Config {
+                id_display,
+                ..Config::default()
+            };
+            task.construct_task_output(&config, Line::Text, Local::now())
+        };
+        assert_eq!(render(IdDisplay::Id), "12 Pay rent");
+        assert_eq!(render(IdDisplay::UuidShort), "5e0f9a3c Pay rent");
+        assert_eq!(render(IdDisplay::None), "Pay rent");
+
+        let config = Config {
+            id_display: IdDisplay::UuidShort,
+            ..Config::default()
+        };
+        assert_eq!(
+            simple_task(3, "No uuid").construct_task_output(&config, Line::Text, Local::now()),
+            "3 No uuid"
+        );
+    }
+
+    #[test]
+    fn test_text_due_within_days() {
+        let now = local_datetime(2024, 12, 3, 12, 0);
+        let tasks = vec![
+            Task {
+                urgency: Some(12.0),
+                due: Some(task_date(local_datetime(2024, 12, 20, 9, 0))),
+                ..simple_task(1, "Tax return")
+            },
+            Task {
+                urgency: Some(8.0),
+                ..simple_task(2, "Read book")
+            },
+            Task {
+                urgency: Some(4.0),
+                due: Some(task_date(local_datetime(2024, 12, 4, 9, 0))),
+                ..simple_task(3, "Pay rent")
+            },
+        ];
+        let config = Config {
+            field_order: vec![Field::Description],
+            text_due_within_days: Some(3.0),
+            show_next: true,
+            ..Config::default()
+        };
+
+        let output = generate_waybar_output_at(&tasks, &[], &config, now);
+        assert_eq!(output.text, "3 Pay rent → (next: Tax return)");
+        assert_eq!(
+            output.tooltip.as_deref(),
+            Some("1 Tax return\n2 Read book\n3 Pay rent")
+        );
+
+        let output = generate_waybar_output_at(&tasks[..2], &[], &config, now);
+        assert_eq!(output.text, "1 Tax return → (next: Read book)");
+    }
+
+    #[test]
+    fn test_rotate_top() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("waybar-tasks.json.rotation");
+        let indices: Vec<usize> = (0..5)
+            .map(|_| advance_rotation(&path, 3).unwrap())
+            .collect();
+        assert_eq!(indices, [0, 1, 2, 0, 1]);
+        assert_eq!(read_rotation(&path), Some(1));
+
+        let tasks: Vec<Task> = (1..=4).map(|id| simple_task(id, "Task")).collect();
+        let text_at = |rotation_index, tasks: &[Task]| {
+            let config = Config {
+                rotate_top: Some(3),
+                rotation_index,
+                ..Config::default()
+            };
+            generate_waybar_output(tasks, &config).text
+        };
+        assert_eq!(text_at(0, &tasks), "1 Task");
+        assert_eq!(text_at(2, &tasks), "3 Task");
+        // Fewer tasks than rotate_top wrap around earlier.
+        assert_eq!(text_at(2, &tasks[..2]), "1 Task");
+    }
+
+    #[test]
+    fn test_show_active_elapsed() {
+        let now = local_datetime(2024, 12, 6, 15, 0);
+        let started = Task {
+            start: Some(task_date(now - chrono::TimeDelta::minutes(25))),
+            ..simple_task(1, "Write report")
+        };
+        let config = Config {
+            show_active_elapsed: true,
+            ..Config::default()
+        };
+        let output = generate_waybar_output_at(std::slice::from_ref(&started), &[], &config, now);
+        assert_eq!(output.text, "1 Write report ▶ 25m");
+
+        let long_ago = Task {
+            start: Some(task_date(now - chrono::TimeDelta::minutes(125))),
+            ..started.clone()
+        };
+        let output = generate_waybar_output_at(&[long_ago], &[], &config, now);
+        assert_eq!(output.text, "1 Write report ▶ 2h 5m");
+
+        let output =
+            generate_waybar_output_at(&[simple_task(1, "Write report")], &[], &config, now);
+        assert_eq!(output.text, "1 Write report");
+
+        let output = generate_waybar_output_at(&[started], &[], &Config::default(), now);
+        assert_eq!(output.text, "1 Write report");
+    }
+
+    #[test]
+    fn test_all_filtered_text() {
+        let config = Config {
+            min_urgency: Some(5.0),
+            ..Config::default()
+        };
+        let low = Task {
+            urgency: Some(2.0),
+            ..simple_task(1, "Water plants")
+        };
+        let output = generate_waybar_output(std::slice::from_ref(&low), &config);
+        assert_eq!(output.text, "No matching tasks.");
+        assert_eq!(output.tooltip.as_deref(), Some("No matching tasks."));
+
+        let output = generate_waybar_output(&[], &config);
+        assert_eq!(output.text, "No tasks.");
+
+        let high = Task {
+            urgency: Some(8.0),
+            ..simple_task(2, "File taxes")
+        };
+        let output = generate_waybar_output(&[low, high], &config);
+        assert_eq!(output.text, "2 File taxes, Urgency: 8.00");
+        assert_eq!(
+            output.tooltip.as_deref(),
+            Some("2 File taxes, Urgency: 8.00")
+        );
+    }
+
+    #[test]
+    fn test_text_template() {
+        let now = local_datetime(2024, 12, 6, 15, 0);
+        let config = Config {
+            text_template: Some(
+                Template::parse("{#if overdue}⚠ {/if}{description}{#if !due} (no date){/if}")
+                    .unwrap(),
+            ),
+            ..Config::default()
+        };
+        let overdue = Task {
+            due: Some(task_date(now - chrono::TimeDelta::hours(2))),
+            ..simple_task(1, "Pay rent")
+        };
+        let upcoming = Task {
+            due: Some(task_date(now + chrono::TimeDelta::days(2))),
+            ..simple_task(1, "Pay rent")
+        };
+        let text = |task: &Task| {
+            generate_waybar_output_at(std::slice::from_ref(task), &[], &config, now).text
+        };
+        assert_eq!(text(&overdue), "⚠ Pay rent");
+        assert_eq!(text(&upcoming), "Pay rent");
+        assert_eq!(text(&simple_task(1, "Pay rent")), "Pay rent (no date)");
+
+        // The tooltip keeps using field_order.
+        let output = generate_waybar_output_at(&[overdue], &[], &config, now);
+        assert!(output.tooltip.unwrap().starts_with("1 Pay rent, Due: "));
+    }
+
+    #[test]
+    fn test_text_template_id_follows_id_display() {
+        let task = Task {
+            uuid: Some("5e0f9a3c-1d2b-4c5d-8e9f-0a1b2c3d4e5f".to_string()),
+            ..simple_task(12, "Pay rent")
+        };
+        let render = |id_display| {
+            let config = Config {
+                id_display,
+                text_template: Some(Template::parse("[{id}] {description}").unwrap()),
+                ..Config::default()
+            };
+            generate_waybar_output(std::slice::from_ref(&task), &config).text
+        };
+        assert_eq!(render(IdDisplay::Id), "[12] Pay rent");
+        assert_eq!(render(IdDisplay::UuidShort), "[5e0f9a3c] Pay rent");
+        assert_eq!(render(IdDisplay::None), "[] Pay rent");
+    }
+
+    /// A task database that is always there, or never with `None`.
+    struct MockSource(Option<Vec<TaskRecord>>);
+
+    impl TaskSource for MockSource {
+        fn pending_records(&self) -> Result<Option<Vec<TaskRecord>>, TaskHookWaybarError> {
+            Ok(self.0.clone())
+        }
+    }
+
+    fn record(id: u32, uuid: &str, properties: &[(&str, &str)]) -> TaskRecord {
+        TaskRecord {
+            id,
+            uuid: uuid.to_string(),
+            properties: properties
+                .iter()
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_load_tasks_from_source() {
+        let source = MockSource(Some(vec![
+            record(
+                2,
+                "b0e5a0c1-0000-4000-8000-000000000002",
+                &[("status", "pending"), ("description", "Buy milk")],
+            ),
+            record(
+                1,
+                "a0e5a0c1-0000-4000-8000-000000000001",
+                &[
+                    ("status", "pending"),
+                    ("description", "Pay rent"),
+                    ("priority", "H"),
+                    ("due", "1733495402"),
+                    ("start", "1733490000"),
+                    ("tag_home", ""),
+                    ("tag_bills", ""),
+                    ("annotation_1733400000", "Ask about the deposit"),
+                ],
+            ),
+        ]));
+        let config = Config {
+            use_context: false,
+            ..Config::default()
+        };
+        let now = Local.timestamp_opt(1733497200, 0).unwrap();
+        let tasks = load_tasks_at(Some(&source), &config, now).unwrap();
+
+        assert_eq!(tasks.len(), 2);
+        let by_id = |id| tasks.iter().find(|task| task.id == id).unwrap();
+        assert_eq!(
+            Task {
+                urgency: None,
+                ..by_id(1).clone()
+            },
+            Task {
+                id: 1,
+                uuid: Some("a0e5a0c1-0000-4000-8000-000000000001".to_string()),
+                status: Some("pending".to_string()),
+                description: Some("Pay rent".to_string()),
+                priority: Some("H".to_string()),
+                due: Some("20241206T143002Z".to_string()),
+                start: Some("20241206T130000Z".to_string()),
+                tags: vec!["bills".to_string(), "home".to_string()],
+                annotations: vec![Annotation {
+                    entry: Some("20241205T120000Z".to_string()),
+                    description: "Ask about the deposit".to_string(),
+                }],
+                ..Task::default()
+            }
+        );
+        assert!(by_id(1).urgency.unwrap() > by_id(2).urgency.unwrap());
+        assert_eq!(by_id(2).description.as_deref(), Some("Buy milk"));
+        // Without an entry date a task counts as old as it gets, like in taskwarrior.
+        assert_eq!(by_id(2).urgency, Some(2.0));
+    }
+
+    #[test]
+    fn test_load_tasks_from_source_matches_export() {
+        const NOW: i64 = 1733497200;
+        const DAY: i64 = 86400;
+        let uuid = |n| format!("00000000-0000-4000-8000-00000000000{}", n);
+        let at = |timestamp: i64| timestamp.to_string();
+        let records = vec![
+            record(
+                3,
+                &uuid(3),
+                &[("description", "Call bank"), ("priority", "L")],
+            ),
+            record(
+                1,
+                &uuid(1),
+                &[
+                    ("description", "Pay rent"),
+                    ("priority", "H"),
+                    ("tag_home", ""),
+                ],
+            ),
+            record(
+                2,
+                &uuid(2),
+                &[("description", "Buy milk"), ("tag_next", "")],
+            ),
+            record(4, &uuid(4), &[("description", "Fix bike")]),
+        ];
+        let records: Vec<TaskRecord> = records
+            .into_iter()
+            .map(|mut record| {
+                let entry = match record.id {
+                    3 => NOW - 400 * DAY,
+                    1 => NOW - 73 * DAY,
+                    _ => NOW,
+                };
+                record.properties.insert("status".into(), "pending".into());
+                record.properties.insert("entry".into(), at(entry));
+                match record.id {
+                    1 => record.properties.insert("due".into(), at(NOW - DAY)),
+                    4 => record
+                        .properties
+                        .insert(format!("dep_{}", uuid(3)), String::new()),
+                    _ => None,
+                };
+                record
+            })
+            .collect();
+        // What `task export` gives for the same tasks, urgency as taskwarrior computes it.
+        let export = format!(
+            r#"[
+            {{"id":4,"uuid":"{u4}","status":"pending","description":"Fix bike","entry":"20241206T150000Z","depends":["{u3}"],"urgency":-5}},
+            {{"id":2,"uuid":"{u2}","status":"pending","description":"Buy milk","entry":"20241206T150000Z","tags":["next"],"urgency":15.8}},
+            {{"id":1,"uuid":"{u1}","status":"pending","description":"Pay rent","priority":"H","due":"20241205T150000Z","entry":"20240924T150000Z","tags":["home"],"urgency":16.4571}},
+            {{"id":3,"uuid":"{u3}","status":"pending","description":"Call bank","priority":"L","entry":"20231102T150000Z","urgency":11.8}}
+            ]"#,
+            u1 = uuid(1),
+            u2 = uuid(2),
+            u3 = uuid(3),
+            u4 = uuid(4),
+        );
+        let config = Config {
+            use_context: false,
+            ..Config::default()
+        };
+        let now = Local.timestamp_opt(NOW, 0).unwrap();
+
+        let from_source = load_tasks_at(Some(&MockSource(Some(records))), &config, now).unwrap();
+        let mut from_export = parse_export(&export).unwrap();
+        sort_tasks_by(&mut from_export, config.sort);
+
+        let ids = |tasks: &[Task]| tasks.iter().map(|task| task.id).collect::<Vec<_>>();
+        assert_eq!(ids(&from_source), [1, 2, 3, 4]);
+        assert_eq!(ids(&from_source), ids(&from_export));
+        assert_eq!(
+            generate_waybar_output_at(&from_source, &[], &config, now),
+            generate_waybar_output_at(&from_export, &[], &config, now)
+        );
+    }
+
+    #[test]
+    fn test_load_tasks_skips_source_for_custom_filters() {
+        struct FailingSource;
+
+        impl TaskSource for FailingSource {
+            fn pending_records(&self) -> Result<Option<Vec<TaskRecord>>, TaskHookWaybarError> {
+                panic!("the source must not be read for a filter only taskwarrior knows");
+            }
+        }
+
+        // No taskwarrior in the tests, the export fails or finds nothing.
+        let config = Config {
+            filter: "status:pending +work".to_string(),
+            use_context: false,
+            ..Config::default()
+        };
+        let _ = load_tasks(Some(&FailingSource), &config);
+        // Only taskwarrior knows the active context.
+        let _ = load_tasks(Some(&FailingSource), &Config::default());
+    }
+
+    #[test]
+    fn test_max_tooltip_bytes() {
+        let tasks: Vec<Task> = (1..=500)
+            .map(|id| simple_task(id, "Überweisung prüfen"))
+            .collect();
+        let config = Config {
+            max_tooltip_bytes: Some(100),
+            ..Config::default()
+        };
+        let tooltip = generate_waybar_output(&tasks, &config).tooltip.unwrap();
+        assert!(tooltip.len() <= 100, "{}", tooltip.len());
+        assert!(tooltip.starts_with("1 Überweisung prüfen\n2 Überweisung prüfen\n"));
+        assert!(tooltip.ends_with('…'));
+        assert!(std::str::from_utf8(tooltip.as_bytes()).is_ok());
+
+        let config = Config {
+            pango_markup: true,
+            ..config
+        };
+        let tooltip = generate_waybar_output(&tasks, &config).tooltip.unwrap();
+        assert!(tooltip.len() <= 100);
+        assert!(tooltip.ends_with("prüfen\n…"), "{}", tooltip);
+
+        let tooltip = generate_waybar_output(&tasks[..2], &config)
+            .tooltip
+            .unwrap();
+        assert!(!tooltip.contains('…'));
+    }
+
+    #[test]
+    fn test_field_order() {
+        let task = Task {
+            priority: Some("H".to_string()),
+            due: Some(task_date(local_datetime(2024, 12, 3, 14, 30))),
+            urgency: Some(3.0),
+            ..simple_task(1, "Pay rent")
+        };
+        let config = Config {
+            field_order: vec![Field::Due, Field::Description],
+            ..Config::default()
+        };
+        assert_eq!(
+            task.construct_task_output(&config, Line::Text, Local::now()),
+            "1 Due: Tue, 24-12-03 14:30, Pay rent"
+        );
+    }
+
+    #[test]
+    fn test_show_next() {
+        let config = Config {
+            show_next: true,
+            ..Config::default()
+        };
+        let tasks = [
+            simple_task(1, "Buy milk"),
+            simple_task(2, "Call the bank about the new card"),
+        ];
+        assert_eq!(
+            generate_waybar_output(&tasks, &config).text,
+            "1 Buy milk → (next: Call the bank about…)"
+        );
+        assert_eq!(
+            generate_waybar_output(&tasks[..1], &config).text,
+            "1 Buy milk"
+        );
+        assert_eq!(
+            generate_waybar_output(&tasks, &Config::default()).text,
+            "1 Buy milk"
+        );
+    }
+
+    #[test]
+    fn test_text_tie_mode() {
+        let config = Config {
+            text_tie_mode: TextTieMode::Count,
+            ..Config::default()
+        };
+        let task = |id, urgency| Task {
+            urgency: Some(urgency),
+            ..simple_task(id, "Task")
+        };
+        let tasks = [task(1, 12.0), task(2, 12.0), task(3, 12.0), task(4, 3.5)];
+        assert_eq!(
+            generate_waybar_output(&tasks, &config).text,
+            "3 tasks at urgency 12.0"
+        );
+        assert_eq!(
+            generate_waybar_output(&tasks, &Config::default()).text,
+            "1 Task, Urgency: 12.00"
+        );
+        assert_eq!(
+            generate_waybar_output(&tasks[2..], &config).text,
+            "3 Task, Urgency: 12.00"
+        );
+    }
+
+    #[test]
+    fn test_compact_when_over() {
+        let task = Task {
+            priority: Some("H".to_string()),
+            due: Some(task_date(local_datetime(2024, 12, 3, 14, 30))),
+            urgency: Some(12.5),
+            ..simple_task(1, "Pay rent")
+        };
+        let full = "1 Pay rent, Prio: H, Due: Tue, 24-12-03 14:30, Urgency: 12.50";
+        assert_eq!(task.render_text(&Config::default(), Local::now()), full);
+
+        // Dropping the urgency is not enough, so the due date goes as well.
+        let config = Config {
+            compact_when_over: Some(25),
+            ..Config::default()
+        };
+        assert_eq!(
+            task.render_text(&config, Local::now()),
+            "1 Pay rent, Prio: H"
+        );
+
+        // Never drops the description, even if it still does not fit.
+        let config = Config {
+            compact_when_over: Some(5),
+            ..Config::default()
+        };
+        assert_eq!(task.render_text(&config, Local::now()), "1 Pay rent");
+
+        let config = Config {
+            compact_when_over: Some(full.chars().count()),
+            ..Config::default()
+        };
+        assert_eq!(task.render_text(&config, Local::now()), full);
+    }
+
+    #[test]
+    fn test_urgency_trim_zeros() {
+        let config = Config {
+            urgency_trim_zeros: true,
+            ..Config::default()
+        };
+        assert_eq!(format_urgency(42.0, &config), "42");
+        assert_eq!(format_urgency(41.999, &config), "42");
+        assert_eq!(format_urgency(4.25, &config), "4.25");
+        assert_eq!(format_urgency(4.5, &config), "4.50");
+        assert_eq!(format_urgency(42.0, &Config::default()), "42.00");
+    }
+
+    #[test]
+    fn test_clamp_negative_urgency() {
+        let config = Config {
+            clamp_negative_urgency: true,
+            ..Config::default()
+        };
+        let mut tasks = vec![
+            Task {
+                urgency: Some(-3.0),
+                ..simple_task(1, "Negative")
+            },
+            Task {
+                urgency: Some(-1.0),
+                ..simple_task(2, "Less negative")
+            },
+        ];
+        sort_tasks_by(&mut tasks, config.sort);
+        assert_eq!(tasks[0].id, 2);
+
+        let now = Local::now();
+        assert_eq!(
+            tasks[1].construct_task_output(&config, Line::Tooltip, now),
+            "1 Negative, Urgency: 0.00"
+        );
+        assert_eq!(
+            tasks[1].construct_task_output(&Config::default(), Line::Tooltip, now),
+            "1 Negative, Urgency: -3.00"
+        );
+    }
+
+    #[test]
+    fn test_urgency_percentage() {
+        assert_eq!(urgency_percentage(2.0, 20.0, PercentageScale::Linear), 10);
+        assert_eq!(urgency_percentage(2.0, 20.0, PercentageScale::Log), 36);
+        for scale in [PercentageScale::Linear, PercentageScale::Log] {
+            assert_eq!(urgency_percentage(20.0, 20.0, scale), 100);
+            assert_eq!(urgency_percentage(45.0, 20.0, scale), 100);
+            assert_eq!(urgency_percentage(-3.0, 20.0, scale), 0);
+            assert_eq!(urgency_percentage(5.0, 0.0, scale), 0);
+        }
+
+        let tasks = [Task {
+            urgency: Some(2.0),
+            ..simple_task(1, "Task")
+        }];
+        assert_eq!(
+            generate_waybar_output(&tasks, &Config::default()).percentage,
+            None
+        );
+        let config = Config {
+            percentage_urgency: Some(20.0),
+            percentage_scale: PercentageScale::Log,
+            ..Config::default()
+        };
+        let output = generate_waybar_output(&tasks, &config);
+        assert_eq!(serde_json::to_value(&output).unwrap()["percentage"], 36);
+    }
+
+    #[test]
+    fn test_urgency_bar() {
+        assert_eq!(urgency_bar(0.0, 12.0, 6), "[░░░░░░]");
+        assert_eq!(urgency_bar(6.0, 12.0, 6), "[███░░░]");
+        assert_eq!(urgency_bar(12.0, 12.0, 6), "[██████]");
+        assert_eq!(urgency_bar(-3.0, 12.0, 6), "[░░░░░░]");
+        assert_eq!(urgency_bar(-3.0, -1.0, 6), "[░░░░░░]");
+
+        let tasks = [
+            Task {
+                urgency: Some(8.0),
+                ..simple_task(1, "First")
+            },
+            Task {
+                urgency: Some(4.0),
+                ..simple_task(2, "Second")
+            },
+        ];
+        let config = Config {
+            urgency_bar: true,
+            ..Config::default()
+        };
+        let output = generate_waybar_output(&tasks, &config);
+        assert_eq!(
+            output.tooltip.as_deref(),
+            Some("[██████] 1 First, Urgency: 8.00\n[███░░░] 2 Second, Urgency: 4.00")
+        );
+        assert_eq!(output.text, "1 First, Urgency: 8.00");
+    }
+
+    #[test]
+    fn test_tag_summary_footer() {
+        let tagged = |id: u32, tags: &[&str]| Task {
+            tags: tags.iter().map(|t| t.to_string()).collect(),
+            ..simple_task(id, "Task")
+        };
+        let tasks = [
+            tagged(1, &["work", "PENDING"]),
+            tagged(2, &["home", "work"]),
+            tagged(3, &["work", "garden", "PENDING"]),
+            tagged(4, &["home"]),
+            tagged(5, &[]),
+        ];
+        let config = Config {
+            tag_summary: true,
+            ..Config::default()
+        };
+
+        let tooltip = generate_waybar_output(&tasks, &config).tooltip.unwrap();
+        assert_eq!(tooltip.lines().last(), Some("+work:3 +home:2 +garden:1"));
+        assert_eq!(tooltip.lines().count(), 6);
+
+        assert_eq!(
+            tag_summary(&tasks, true).as_deref(),
+            Some("+work:3 +PENDING:2 +home:2 +garden:1")
+        );
+        assert_eq!(tag_summary(&tasks[4..], false), None);
+    }
+
     #[test]
     fn test_sort_tasks() {
         let mut tasks = vec![
@@ -236,6 +3488,7 @@ pub mod tests {
                 priority: Some("H".to_string()),
                 due: Some("20241206T143002Z".to_string()),
                 urgency: Some(3.0),
+                ..Task::default()
             },
             Task {
                 id: 2,
@@ -243,6 +3496,7 @@ pub mod tests {
                 priority: Some("M".to_string()),
                 due: Some("20241205T143002Z".to_string()),
                 urgency: Some(5.0),
+                ..Task::default()
             },
             Task {
                 id: 3,
@@ -250,6 +3504,7 @@ pub mod tests {
                 priority: Some("L".to_string()),
                 due: Some("20241207T143002Z".to_string()),
                 urgency: None,
+                ..Task::default()
             },
             Task {
                 id: 4,
@@ -257,6 +3512,7 @@ pub mod tests {
                 priority: None,
                 due: None,
                 urgency: Some(2.0),
+                ..Task::default()
             },
             Task {
                 id: 5,
@@ -264,6 +3520,7 @@ pub mod tests {
                 priority: None,
                 due: Some("20231205T143002Z".to_string()),
                 urgency: Some(5.0),
+                ..Task::default()
             },
             Task {
                 id: 6,
@@ -271,6 +3528,7 @@ pub mod tests {
                 priority: None,
                 due: Some("20231205T143002Z".to_string()),
                 urgency: Some(5.0),
+                ..Task::default()
             },
         ];
 
@@ -283,6 +3541,7 @@ pub mod tests {
                 priority: None,
                 due: Some("20231205T143002Z".to_string()),
                 urgency: Some(5.0),
+                ..Task::default()
             },
             Task {
                 id: 6,
@@ -290,6 +3549,7 @@ pub mod tests {
                 priority: None,
                 due: Some("20231205T143002Z".to_string()),
                 urgency: Some(5.0),
+                ..Task::default()
             },
             Task {
                 id: 2,
@@ -297,6 +3557,7 @@ pub mod tests {
                 priority: Some("M".to_string()),
                 due: Some("20241205T143002Z".to_string()),
                 urgency: Some(5.0),
+                ..Task::default()
             },
             Task {
                 id: 1,
@@ -304,6 +3565,7 @@ pub mod tests {
                 priority: Some("H".to_string()),
                 due: Some("20241206T143002Z".to_string()),
                 urgency: Some(3.0),
+                ..Task::default()
             },
             Task {
                 id: 4,
@@ -311,6 +3573,7 @@ pub mod tests {
                 priority: None,
                 due: None,
                 urgency: Some(2.0),
+                ..Task::default()
             },
             Task {
                 id: 3,
@@ -318,6 +3581,7 @@ pub mod tests {
                 priority: Some("L".to_string()),
                 due: Some("20241207T143002Z".to_string()),
                 urgency: None,
+                ..Task::default()
             },
         ];
 
@@ -0,0 +1,236 @@
+use crate::errors::TaskHookWaybarError;
+use std::collections::HashMap;
+use std::process::Command;
+
+/// Taskwarrior's default for `rule.precedence.color`, highest precedence first.
+const DEFAULT_PRECEDENCE: &str = "deleted,completed,active,keyword.,tag.,project.,overdue,\
+                                  scheduled,due.today,due,blocked,blocking,recurring,tagged,uda.";
+
+const NAMED_COLORS: [&str; 8] = [
+    "black", "red", "green", "yellow", "blue", "magenta", "cyan", "white",
+];
+
+/// The 16 basic colors as xterm shows them.
+const BASIC_COLORS: [(u8, u8, u8); 16] = [
+    (0x00, 0x00, 0x00),
+    (0xcd, 0x00, 0x00),
+    (0x00, 0xcd, 0x00),
+    (0xcd, 0xcd, 0x00),
+    (0x00, 0x00, 0xee),
+    (0xcd, 0x00, 0xcd),
+    (0x00, 0xcd, 0xcd),
+    (0xe5, 0xe5, 0xe5),
+    (0x7f, 0x7f, 0x7f),
+    (0xff, 0x00, 0x00),
+    (0x00, 0xff, 0x00),
+    (0xff, 0xff, 0x00),
+    (0x5c, 0x5c, 0xff),
+    (0xff, 0x00, 0xff),
+    (0x00, 0xff, 0xff),
+    (0xff, 0xff, 0xff),
+];
+
+const CUBE_LEVELS: [u8; 6] = [0, 95, 135, 175, 215, 255];
+
+/// The foreground colors of taskwarrior's color rules, so the bar colors tasks like
+/// `task next` does. Only the rules for the active state, tags and priorities are used.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TaskColors {
+    /// The rules that have a color, by name without the `color.` prefix, like `tag.home`.
+    colors: HashMap<String, String>,
+    precedence: Vec<String>,
+}
+
+impl TaskColors {
+    /// Reads the color rules with a single `task _show`, instead of asking for every task.
+    pub fn load() -> Result<Self, TaskHookWaybarError> {
+        let output = Command::new("task")
+            .args(["rc.hooks:off", "_show"])
+            .output()?;
+        Ok(Self::parse(&String::from_utf8_lossy(&output.stdout)))
+    }
+
+    /// Parses the `name=value` lines of `task _show`. Rules whose foreground is no color
+    /// this understands are skipped.
+    pub fn parse(show_output: &str) -> Self {
+        let mut colors = HashMap::new();
+        let mut precedence = DEFAULT_PRECEDENCE;
+        for (name, value) in show_output.lines().filter_map(|line| line.split_once('=')) {
+            if name == "rule.precedence.color" {
+                precedence = value;
+            } else if let Some(rule) = name.strip_prefix("color.") {
+                if let Some(color) = foreground_hex(value) {
+                    colors.insert(rule.to_string(), color);
+                }
+            }
+        }
+        TaskColors {
+            colors,
+            precedence: precedence
+                .split(',')
+                .map(|rule| rule.trim().to_string())
+                .collect(),
+        }
+    }
+
+    /// The color of the first rule in `rule.precedence.color` the task matches.
+    pub fn color_for(&self, tags: &[String], priority: Option<&str>, active: bool) -> Option<&str> {
+        self.precedence.iter().find_map(|rule| {
+            let color = match rule.as_str() {
+                "active" if active => self.colors.get("active"),
+                "tagged" if !tags.is_empty() => self.colors.get("tagged"),
+                "tag." => tags
+                    .iter()
+                    .find_map(|tag| self.colors.get(&format!("tag.{}", tag))),
+                "uda." => priority.and_then(|p| self.colors.get(&format!("uda.priority.{}", p))),
+                _ => None,
+            };
+            color.map(String::as_str)
+        })
+    }
+}
+
+/// The foreground of a taskwarrior color like `bold red on blue`, `color214`, `rgb520`
+/// or `gray12` as `#rrggbb`.
+fn foreground_hex(spec: &str) -> Option<String> {
+    let spec = spec.trim();
+    let foreground = match spec.split_once(" on ") {
+        Some((foreground, _)) => foreground,
+        None if spec.starts_with("on ") => return None,
+        None => spec,
+    };
+    let mut bright = false;
+    let mut index = None;
+    for word in foreground.split_whitespace() {
+        match word {
+            "bright" => bright = true,
+            "bold" | "underline" | "inverse" => {}
+            word => index = Some(color_index(word)?),
+        }
+    }
+    let index = match index? {
+        named @ 0..=7 if bright => named + 8,
+        index => index,
+    };
+    let (r, g, b) = xterm_rgb(index);
+    Some(format!("#{:02x}{:02x}{:02x}", r, g, b))
+}
+
+/// The xterm palette index of a single color word.
+fn color_index(word: &str) -> Option<u8> {
+    if let Some(position) = NAMED_COLORS.iter().position(|name| *name == word) {
+        return Some(position as u8);
+    }
+    if let Some(index) = word.strip_prefix("color") {
+        return index.parse().ok();
+    }
+    if let Some(level) = word
+        .strip_prefix("gray")
+        .or_else(|| word.strip_prefix("grey"))
+    {
+        return level
+            .parse::<u8>()
+            .ok()
+            .filter(|level| *level < 24)
+            .map(|level| 232 + level);
+    }
+    let digits: Vec<u8> = word
+        .strip_prefix("rgb")?
+        .chars()
+        .map(|c| c.to_digit(10).filter(|d| *d < 6).map(|d| d as u8))
+        .collect::<Option<_>>()?;
+    match digits[..] {
+        [r, g, b] => Some(16 + 36 * r + 6 * g + b),
+        _ => None,
+    }
+}
+
+fn xterm_rgb(index: u8) -> (u8, u8, u8) {
+    match index {
+        0..=15 => BASIC_COLORS[index as usize],
+        16..=231 => {
+            let cube = index - 16;
+            (
+                CUBE_LEVELS[(cube / 36) as usize],
+                CUBE_LEVELS[(cube / 6 % 6) as usize],
+                CUBE_LEVELS[(cube % 6) as usize],
+            )
+        }
+        gray => {
+            let level = 8 + 10 * (gray - 232);
+            (level, level, level)
+        }
+    }
+}
+
+/**************
+ * Unit tests *
+ **************/
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SHOW_OUTPUT: &str = "\
+color=on
+color.active=rgb555 on rgb410
+color.tag.next=rgb440
+color.tag.home=bold bright green
+color.tagged=color246
+color.uda.priority.H=color255
+color.uda.priority.L=on gray5
+color.overdue=color1
+rule.precedence.color=active,tag.,uda.,tagged
+";
+
+    fn tags(tags: &[&str]) -> Vec<String> {
+        tags.iter().map(|tag| tag.to_string()).collect()
+    }
+
+    #[test]
+    fn test_foreground_hex() {
+        assert_eq!(foreground_hex("red").as_deref(), Some("#cd0000"));
+        assert_eq!(
+            foreground_hex("bold bright red on blue").as_deref(),
+            Some("#ff0000")
+        );
+        assert_eq!(foreground_hex("rgb520").as_deref(), Some("#ff8700"));
+        assert_eq!(foreground_hex("color214").as_deref(), Some("#ffaf00"));
+        assert_eq!(foreground_hex("gray12").as_deref(), Some("#808080"));
+        assert_eq!(foreground_hex("on red"), None);
+        assert_eq!(foreground_hex("rgb600"), None);
+        assert_eq!(foreground_hex("underline"), None);
+    }
+
+    #[test]
+    fn test_color_for_follows_precedence() {
+        let colors = TaskColors::parse(SHOW_OUTPUT);
+
+        assert_eq!(
+            colors.color_for(&tags(&["next"]), Some("H"), true),
+            Some("#ffffff")
+        );
+        assert_eq!(
+            colors.color_for(&tags(&["next"]), Some("H"), false),
+            Some("#d7d700")
+        );
+        assert_eq!(
+            colors.color_for(&tags(&["home"]), None, false),
+            Some("#00ff00")
+        );
+        assert_eq!(
+            colors.color_for(&tags(&["work"]), Some("H"), false),
+            Some("#eeeeee")
+        );
+        assert_eq!(
+            colors.color_for(&tags(&["work"]), None, false),
+            Some("#949494")
+        );
+        // Rules without a foreground and those not in the precedence are skipped.
+        assert_eq!(colors.color_for(&[], Some("L"), false), None);
+        assert_eq!(
+            TaskColors::default().color_for(&tags(&["next"]), None, true),
+            None
+        );
+    }
+}
@@ -0,0 +1,217 @@
+use crate::errors::TemplateError;
+use serde::{Deserialize, Serialize};
+
+/// The placeholders a template may use, like `{description}`.
+pub const PLACEHOLDERS: [&str; 6] = ["id", "description", "tags", "priority", "due", "urgency"];
+
+/// The conditions of `{#if ...}` blocks. `{#if !overdue}` negates one.
+pub const CONDITIONS: [&str; 4] = ["overdue", "due", "active", "tagged"];
+
+/// A parsed `text_template`.
+///
+/// * `{name}` is replaced by the value of a placeholder, or nothing if the task has none.
+/// * `{#if condition}...{/if}` keeps its content only if the condition holds. Blocks nest.
+/// * `{{` and `}}` are a literal `{` and `}`.
+///
+/// In the config it is written as its source, which is parsed when the config is loaded.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[serde(try_from = "String", into = "String")]
+pub struct Template {
+    source: String,
+    nodes: Vec<Node>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Node {
+    Text(String),
+    Placeholder(&'static str),
+    If {
+        condition: &'static str,
+        negated: bool,
+        body: Vec<Node>,
+    },
+}
+
+/// The condition of an `{#if}` block and whether it is negated, `None` for the template
+/// itself, with the nodes inside it.
+type OpenBlock = (Option<(&'static str, bool)>, Vec<Node>);
+
+impl Template {
+    pub fn parse(source: &str) -> Result<Self, TemplateError> {
+        // The open blocks, innermost last, each with the nodes collected so far.
+        let mut stack: Vec<OpenBlock> = vec![(None, Vec::new())];
+        let mut text = String::new();
+        let mut rest = source;
+        while let Some(position) = rest.find(['{', '}']) {
+            text.push_str(&rest[..position]);
+            let tail = &rest[position..];
+            if tail.starts_with("{{") || tail.starts_with("}}") {
+                text.push_str(&tail[..1]);
+                rest = &tail[2..];
+                continue;
+            }
+            if let Some(after) = tail.strip_prefix('}') {
+                text.push('}');
+                rest = after;
+                continue;
+            }
+            let end = tail.find('}').ok_or(TemplateError::UnclosedBrace)?;
+            let tag = tail[1..end].trim();
+            rest = &tail[end + 1..];
+
+            let nodes = &mut stack.last_mut().expect("the root is never popped").1;
+            if !text.is_empty() {
+                nodes.push(Node::Text(std::mem::take(&mut text)));
+            }
+            if let Some(condition) = tag.strip_prefix("#if ") {
+                let condition = condition.trim();
+                let (negated, name) = match condition.strip_prefix('!') {
+                    Some(name) => (true, name.trim()),
+                    None => (false, condition),
+                };
+                let name = known(&CONDITIONS, name)
+                    .ok_or_else(|| TemplateError::UnknownCondition(name.to_string()))?;
+                stack.push((Some((name, negated)), Vec::new()));
+            } else if tag == "/if" {
+                let (Some((condition, negated)), body) =
+                    stack.pop().expect("the root is never popped")
+                else {
+                    return Err(TemplateError::UnmatchedEnd);
+                };
+                stack
+                    .last_mut()
+                    .expect("the root is never popped")
+                    .1
+                    .push(Node::If {
+                        condition,
+                        negated,
+                        body,
+                    });
+            } else {
+                let name = known(&PLACEHOLDERS, tag)
+                    .ok_or_else(|| TemplateError::UnknownPlaceholder(tag.to_string()))?;
+                nodes.push(Node::Placeholder(name));
+            }
+        }
+        text.push_str(rest);
+
+        let (open, mut nodes) = stack.pop().expect("the root is never popped");
+        if let Some((condition, _)) = open {
+            return Err(TemplateError::Unclosed(condition.to_string()));
+        }
+        if !text.is_empty() {
+            nodes.push(Node::Text(text));
+        }
+        Ok(Template {
+            source: source.to_string(),
+            nodes,
+        })
+    }
+
+    /// Renders the template, asking `value` for placeholders and `holds` for conditions.
+    pub fn render(
+        &self,
+        value: &dyn Fn(&str) -> Option<String>,
+        holds: &dyn Fn(&str) -> bool,
+    ) -> String {
+        let mut output = String::new();
+        render_nodes(&self.nodes, value, holds, &mut output);
+        output
+    }
+}
+
+impl TryFrom<String> for Template {
+    type Error = TemplateError;
+
+    fn try_from(source: String) -> Result<Self, Self::Error> {
+        Template::parse(&source)
+    }
+}
+
+impl From<Template> for String {
+    fn from(template: Template) -> Self {
+        template.source
+    }
+}
+
+fn known(names: &[&'static str], name: &str) -> Option<&'static str> {
+    names.iter().copied().find(|known| *known == name)
+}
+
+fn render_nodes(
+    nodes: &[Node],
+    value: &dyn Fn(&str) -> Option<String>,
+    holds: &dyn Fn(&str) -> bool,
+    output: &mut String,
+) {
+    for node in nodes {
+        match node {
+            Node::Text(text) => output.push_str(text),
+            Node::Placeholder(name) => output.push_str(&value(name).unwrap_or_default()),
+            Node::If {
+                condition,
+                negated,
+                body,
+            } => {
+                if holds(condition) != *negated {
+                    render_nodes(body, value, holds, output);
+                }
+            }
+        }
+    }
+}
+
+/**************
+ * Unit tests *
+ **************/
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn render(source: &str, overdue: bool) -> String {
+        let template = Template::parse(source).unwrap();
+        template.render(
+            &|name| (name == "description").then(|| "Pay rent".to_string()),
+            &|condition| condition == "overdue" && overdue,
+        )
+    }
+
+    #[test]
+    fn test_render() {
+        assert_eq!(render("{description}", false), "Pay rent");
+        assert_eq!(render("{ description } {due}", false), "Pay rent ");
+        assert_eq!(render("{{literal}} }", false), "{literal} }");
+        assert_eq!(
+            render("{#if overdue}! {/if}{description}", true),
+            "! Pay rent"
+        );
+        assert_eq!(
+            render("{#if overdue}! {/if}{description}", false),
+            "Pay rent"
+        );
+        assert_eq!(render("{#if !overdue}ok{/if}", false), "ok");
+        assert_eq!(render("{#if overdue}a{#if active}b{/if}c{/if}", true), "ac");
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert_eq!(
+            Template::parse("{title}"),
+            Err(TemplateError::UnknownPlaceholder("title".to_string()))
+        );
+        assert_eq!(
+            Template::parse("{#if late}x{/if}"),
+            Err(TemplateError::UnknownCondition("late".to_string()))
+        );
+        assert_eq!(Template::parse("x{/if}"), Err(TemplateError::UnmatchedEnd));
+        assert_eq!(
+            Template::parse("{#if overdue}{#if due}x{/if}"),
+            Err(TemplateError::Unclosed("overdue".to_string()))
+        );
+        assert_eq!(
+            Template::parse("{description"),
+            Err(TemplateError::UnclosedBrace)
+        );
+    }
+}
@@ -0,0 +1,239 @@
+use crate::task::TaskRecord;
+use std::collections::HashSet;
+
+// Taskwarrior's default `urgency.*.coefficient`s.
+const NEXT_TAG_COEFFICIENT: f64 = 15.0;
+const DUE_COEFFICIENT: f64 = 12.0;
+const BLOCKING_COEFFICIENT: f64 = 8.0;
+const SCHEDULED_COEFFICIENT: f64 = 5.0;
+const ACTIVE_COEFFICIENT: f64 = 4.0;
+const AGE_COEFFICIENT: f64 = 2.0;
+const ANNOTATIONS_COEFFICIENT: f64 = 1.0;
+const TAGS_COEFFICIENT: f64 = 1.0;
+const PROJECT_COEFFICIENT: f64 = 1.0;
+const BLOCKED_COEFFICIENT: f64 = -5.0;
+/// `urgency.age.max`, the age in days from which a task counts as old as it gets.
+const AGE_MAX_DAYS: i64 = 365;
+
+const DAY: f64 = 86400.0;
+
+/// `urgency.uda.priority.*.coefficient`.
+fn priority_coefficient(priority: &str) -> f64 {
+    match priority {
+        "H" => 6.0,
+        "M" => 3.9,
+        "L" => 1.8,
+        _ => 0.0,
+    }
+}
+
+/// The urgency taskwarrior exports for each of the pending `records` with its default
+/// coefficients, in the order of `records`. They all have to be passed at once, as a task
+/// blocking or blocked by another pending task counts too. `now` is a unix timestamp.
+pub fn record_urgencies(records: &[TaskRecord], now: i64) -> Vec<f64> {
+    let pending: HashSet<&str> = records.iter().map(|record| record.uuid.as_str()).collect();
+    let blocking: HashSet<&str> = records
+        .iter()
+        .flat_map(|record| pending_dependencies(record, &pending))
+        .collect();
+
+    records
+        .iter()
+        .map(|record| {
+            let property = |name: &str| record.properties.get(name).map(String::as_str);
+            let timestamp = |name: &str| property(name).and_then(|value| value.parse::<i64>().ok());
+            let tags = record
+                .properties
+                .keys()
+                .filter(|key| key.starts_with("tag_"))
+                .count();
+            let annotations = record
+                .properties
+                .keys()
+                .filter(|key| key.starts_with("annotation_"))
+                .count();
+
+            let terms = [
+                (
+                    NEXT_TAG_COEFFICIENT,
+                    flag(record.properties.contains_key("tag_next")),
+                ),
+                (
+                    DUE_COEFFICIENT,
+                    timestamp("due").map_or(0.0, |due| due_term(due, now)),
+                ),
+                (
+                    BLOCKING_COEFFICIENT,
+                    flag(blocking.contains(record.uuid.as_str())),
+                ),
+                (
+                    SCHEDULED_COEFFICIENT,
+                    flag(timestamp("scheduled").is_some_and(|scheduled| scheduled < now)),
+                ),
+                (ACTIVE_COEFFICIENT, flag(property("start").is_some())),
+                (
+                    AGE_COEFFICIENT,
+                    timestamp("entry").map_or(1.0, |entry| age_term(entry, now)),
+                ),
+                (ANNOTATIONS_COEFFICIENT, count_term(annotations)),
+                (TAGS_COEFFICIENT, count_term(tags)),
+                (PROJECT_COEFFICIENT, flag(property("project").is_some())),
+                (
+                    BLOCKED_COEFFICIENT,
+                    flag(!pending_dependencies(record, &pending).is_empty()),
+                ),
+            ];
+            let priority = property("priority").map_or(0.0, priority_coefficient);
+            priority
+                + terms
+                    .iter()
+                    .map(|(coefficient, term)| coefficient * term)
+                    .sum::<f64>()
+        })
+        .collect()
+}
+
+/// The uuids of the pending tasks `record` depends on.
+fn pending_dependencies<'a>(record: &'a TaskRecord, pending: &HashSet<&str>) -> Vec<&'a str> {
+    record
+        .properties
+        .keys()
+        .filter_map(|key| key.strip_prefix("dep_"))
+        .filter(|uuid| pending.contains(uuid))
+        .collect()
+}
+
+fn flag(set: bool) -> f64 {
+    if set {
+        1.0
+    } else {
+        0.0
+    }
+}
+
+/// From 0.2 two weeks before the due date up to 1.0 a week after it.
+fn due_term(due: i64, now: i64) -> f64 {
+    let days_overdue = (now - due) as f64 / DAY;
+    if days_overdue >= 7.0 {
+        1.0
+    } else if days_overdue >= -14.0 {
+        (days_overdue + 14.0) * 0.8 / 21.0 + 0.2
+    } else {
+        0.2
+    }
+}
+
+/// The age in whole days as a share of `AGE_MAX_DAYS`.
+fn age_term(entry: i64, now: i64) -> f64 {
+    let age = (now - entry) / DAY as i64;
+    if age > AGE_MAX_DAYS {
+        1.0
+    } else {
+        age as f64 / AGE_MAX_DAYS as f64
+    }
+}
+
+/// For tags and annotations: 0.8 for one, 0.9 for two and 1.0 for more.
+fn count_term(count: usize) -> f64 {
+    match count {
+        0 => 0.0,
+        1 => 0.8,
+        2 => 0.9,
+        _ => 1.0,
+    }
+}
+
+/**************
+ * Unit tests *
+ **************/
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOW: i64 = 1733497200;
+    const DAYS: i64 = 86400;
+
+    fn record(uuid: &str, properties: &[(&str, String)]) -> TaskRecord {
+        TaskRecord {
+            id: 0,
+            uuid: uuid.to_string(),
+            properties: properties
+                .iter()
+                .map(|(key, value)| (key.to_string(), value.clone()))
+                .collect(),
+        }
+    }
+
+    fn assert_close(actual: &[f64], expected: &[f64]) {
+        assert_eq!(actual.len(), expected.len());
+        for (actual, expected) in actual.iter().zip(expected) {
+            assert!(
+                (actual - expected).abs() < 1e-6,
+                "{} != {}",
+                actual,
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn test_record_urgencies() {
+        let records = [
+            record(
+                "a",
+                &[
+                    ("priority", "H".to_string()),
+                    ("due", (NOW - DAYS).to_string()),
+                    ("entry", (NOW - 73 * DAYS).to_string()),
+                    ("tag_home", String::new()),
+                ],
+            ),
+            record(
+                "b",
+                &[("tag_next", String::new()), ("entry", NOW.to_string())],
+            ),
+            record(
+                "c",
+                &[
+                    ("priority", "L".to_string()),
+                    ("entry", (NOW - 400 * DAYS).to_string()),
+                    ("project", "bank".to_string()),
+                    ("annotation_1733400000", "Ask for the form".to_string()),
+                ],
+            ),
+            record(
+                "d",
+                &[
+                    ("entry", NOW.to_string()),
+                    ("dep_c", String::new()),
+                    ("dep_gone", String::new()),
+                    ("start", NOW.to_string()),
+                    ("scheduled", (NOW - DAYS).to_string()),
+                ],
+            ),
+        ];
+
+        assert_close(
+            &record_urgencies(&records, NOW),
+            &[
+                // Priority, due a day ago, 73 of 365 days old and one tag.
+                6.0 + 12.0 * (15.0 * 0.8 / 21.0 + 0.2) + 2.0 * 0.2 + 0.8,
+                // The next tag is a tag too.
+                15.0 + 0.8,
+                // Blocking d, older than urgency.age.max, a project and an annotation.
+                1.8 + 8.0 + 2.0 + 1.0 + 0.8,
+                // Blocked by c though not by the task that is no longer pending.
+                -5.0 + 4.0 + 5.0,
+            ],
+        );
+    }
+
+    #[test]
+    fn test_due_term() {
+        assert_eq!(due_term(NOW - 8 * DAYS, NOW), 1.0);
+        assert_eq!(due_term(NOW + 30 * DAYS, NOW), 0.2);
+        assert!((due_term(NOW + 14 * DAYS, NOW) - 0.2).abs() < 1e-9);
+        assert!((due_term(NOW - 7 * DAYS, NOW) - 1.0).abs() < 1e-9);
+    }
+}
@@ -1,12 +1,31 @@
 use crate::errors::TaskHookWaybarError;
 use chrono::{Local, Utc};
-use log::info;
+use log::{debug, info, warn};
 use simplelog::*;
-use std::fs::File;
-use std::path::PathBuf;
+use std::ffi::CString;
+use std::fs::{self, File};
+use std::io::{ErrorKind, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant, SystemTime};
 
+static LOGGING_INITIALIZED: AtomicBool = AtomicBool::new(false);
+
+/// Sets up logging to stderr and `log_file_path`.
+///
+/// Calling this again is not an error, the logger from the first call is kept.
 pub fn setup_logging(log_file_path: &PathBuf) -> Result<(), TaskHookWaybarError> {
-    CombinedLogger::init(vec![
+    if LOGGING_INITIALIZED.load(Ordering::Acquire) {
+        debug!(
+            "Logging already initialized, not switching to {}",
+            log_file_path.display()
+        );
+        return Ok(());
+    }
+
+    let result = CombinedLogger::init(vec![
         TermLogger::new(
             LevelFilter::Error,
             Config::default(),
@@ -16,9 +35,15 @@ pub fn setup_logging(log_file_path: &PathBuf) -> Result<(), TaskHookWaybarError>
         WriteLogger::new(
             LevelFilter::Info,
             Config::default(),
-            File::create(log_file_path)?,
+            File::create(log_file_path).map_err(TaskHookWaybarError::with_path(log_file_path))?,
         ),
-    ])?;
+    ]);
+    LOGGING_INITIALIZED.store(true, Ordering::Release);
+
+    if let Err(e) = result {
+        debug!("Logger already set, keeping it: {}", e);
+        return Ok(());
+    }
 
     let time_zone = if Utc::now().timestamp() == Local::now().timestamp() {
         "UTC"
@@ -33,3 +58,521 @@ pub fn setup_logging(log_file_path: &PathBuf) -> Result<(), TaskHookWaybarError>
     info!("Log file time zone: {}", time_zone);
     Ok(())
 }
+
+/// Sets up logging once, then calls `iteration` with its index `iterations` times, waiting
+/// `interval` in between. For a loop like a watch mode, whose iterations reuse the logger
+/// instead of setting it up again. Stops at the first iteration that fails.
+pub fn run_iterations<F>(
+    log_file_path: &PathBuf,
+    iterations: usize,
+    interval: Duration,
+    mut iteration: F,
+) -> Result<(), TaskHookWaybarError>
+where
+    F: FnMut(usize) -> Result<(), TaskHookWaybarError>,
+{
+    setup_logging(log_file_path)?;
+    for index in 0..iterations {
+        if index > 0 {
+            std::thread::sleep(interval);
+        }
+        iteration(index)?;
+    }
+    Ok(())
+}
+
+/// The user's cache directory, or the temp directory if there is none.
+pub fn resolve_cache_dir() -> PathBuf {
+    resolve_cache_dir_with(dirs::cache_dir)
+}
+
+/// Called before logging is set up (the log lives in the cache directory), so the
+/// fallback is reported on stderr.
+fn resolve_cache_dir_with<F>(resolver: F) -> PathBuf
+where
+    F: FnOnce() -> Option<PathBuf>,
+{
+    resolver().unwrap_or_else(|| {
+        let temp_dir = std::env::temp_dir();
+        eprintln!(
+            "Failed to determine cache directory, using {} instead",
+            temp_dir.display()
+        );
+        temp_dir
+    })
+}
+
+/// Shortens `text` to at most `max_chars` characters, ending in `…` if anything was cut.
+pub fn truncate_chars(text: &str, max_chars: usize) -> String {
+    if text.chars().count() <= max_chars {
+        return text.to_string();
+    }
+    if max_chars == 0 {
+        return String::new();
+    }
+    let mut truncated: String = text.chars().take(max_chars - 1).collect();
+    truncated.push('…');
+    truncated
+}
+
+/// The longest prefix of `text` that is at most `max_bytes` long and ends on a character
+/// boundary.
+pub fn prefix_within_bytes(text: &str, max_bytes: usize) -> &str {
+    if text.len() <= max_bytes {
+        return text;
+    }
+    let end = (0..=max_bytes)
+        .rev()
+        .find(|end| text.is_char_boundary(*end))
+        .unwrap_or(0);
+    &text[..end]
+}
+
+/// Returns the last `count` lines of the file at `path`.
+pub fn tail_lines(path: &Path, count: usize) -> Result<Vec<String>, TaskHookWaybarError> {
+    let content = fs::read_to_string(path).map_err(TaskHookWaybarError::with_path(path))?;
+    let lines: Vec<&str> = content.lines().collect();
+    let start = lines.len().saturating_sub(count);
+    Ok(lines[start..].iter().map(|line| line.to_string()).collect())
+}
+
+/// How long ago `path` was last modified, as seen from `now`.
+pub fn file_age(path: &Path, now: SystemTime) -> Result<Duration, TaskHookWaybarError> {
+    let modified = fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .map_err(TaskHookWaybarError::with_path(path))?;
+    Ok(now.duration_since(modified).unwrap_or_default())
+}
+
+/// Fails with a hint if `dir` exists but files cannot be created in it.
+///
+/// A missing `dir` is left for the following write to report.
+pub fn ensure_writable_dir(dir: &Path) -> Result<(), TaskHookWaybarError> {
+    let Ok(metadata) = fs::metadata(dir) else {
+        return Ok(());
+    };
+    let writable = !metadata.permissions().readonly()
+        && CString::new(dir.as_os_str().as_bytes())
+            .is_ok_and(|dir| unsafe { libc::access(dir.as_ptr(), libc::W_OK) } == 0);
+    if writable {
+        return Ok(());
+    }
+    Err(TaskHookWaybarError::with_path(dir)(std::io::Error::new(
+        ErrorKind::PermissionDenied,
+        "the directory is not writable, fix its permissions or set `output` \
+         to a path in a writable directory",
+    )))
+}
+
+/// Expands a leading `~` and `$VAR`/`${VAR}` references in `path` using the environment.
+pub fn expand_path(path: &Path) -> PathBuf {
+    expand_path_with(path, dirs::home_dir().as_deref(), |name| {
+        std::env::var(name).ok()
+    })
+}
+
+/// Undefined variables (and `~` without a home directory) are left in place and logged.
+fn expand_path_with<F>(path: &Path, home: Option<&Path>, lookup: F) -> PathBuf
+where
+    F: Fn(&str) -> Option<String>,
+{
+    let Some(raw) = path.to_str() else {
+        return path.to_path_buf();
+    };
+
+    let mut expanded = String::with_capacity(raw.len());
+    let mut rest = raw;
+
+    if rest == "~" || rest.starts_with("~/") {
+        match home.and_then(Path::to_str) {
+            Some(home) => {
+                expanded.push_str(home);
+                rest = &rest[1..];
+            }
+            None => warn!("Cannot expand ~ in {}, no home directory", raw),
+        }
+    }
+
+    while let Some(start) = rest.find('$') {
+        expanded.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+
+        let (name, reference_len) = match after.strip_prefix('{') {
+            Some(braced) => match braced.find('}') {
+                Some(end) => (&braced[..end], end + 3),
+                None => ("", 1),
+            },
+            None => {
+                let end = after
+                    .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                    .unwrap_or(after.len());
+                (&after[..end], end + 1)
+            }
+        };
+
+        let reference = &rest[start..start + reference_len];
+        match (name.is_empty(), lookup(name)) {
+            (false, Some(value)) => expanded.push_str(&value),
+            (false, None) => {
+                warn!("Undefined variable {} in {}", reference, raw);
+                expanded.push_str(reference);
+            }
+            (true, _) => expanded.push_str(reference),
+        }
+        rest = &rest[start + reference_len..];
+    }
+    expanded.push_str(rest);
+
+    PathBuf::from(expanded)
+}
+
+/// Wall-clock durations of the steps of one run, for `--timings`.
+#[derive(Debug, Default)]
+pub struct Timings {
+    steps: Vec<(&'static str, Duration)>,
+}
+
+impl Timings {
+    /// Runs `f`, logging and recording how long it took as `step`.
+    pub fn time<T>(&mut self, step: &'static str, f: impl FnOnce() -> T) -> T {
+        let start = Instant::now();
+        let result = f();
+        let elapsed = start.elapsed();
+        debug!("{} took {:?}", step, elapsed);
+        self.steps.push((step, elapsed));
+        result
+    }
+
+    /// One line per step and a total, in milliseconds.
+    pub fn summary(&self) -> String {
+        let width = self
+            .steps
+            .iter()
+            .map(|(step, _)| step.len())
+            .chain(std::iter::once("total".len()))
+            .max()
+            .unwrap_or_default();
+        let total: Duration = self.steps.iter().map(|(_, elapsed)| *elapsed).sum();
+
+        self.steps
+            .iter()
+            .chain(std::iter::once(&("total", total)))
+            .map(|(step, elapsed)| {
+                format!(
+                    "{:<width$}  {:>8.1} ms\n",
+                    step,
+                    elapsed.as_secs_f64() * 1000.0,
+                    width = width
+                )
+            })
+            .collect()
+    }
+}
+
+/// Passes writes through to `out`, or drops them for `--quiet`.
+pub struct QuietWriter<W: Write> {
+    out: W,
+    quiet: bool,
+}
+
+impl<W: Write> QuietWriter<W> {
+    pub fn new(out: W, quiet: bool) -> Self {
+        QuietWriter { out, quiet }
+    }
+}
+
+impl<W: Write> Write for QuietWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if self.quiet {
+            return Ok(buf.len());
+        }
+        self.out.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.out.flush()
+    }
+}
+
+/// How often a waiting [`RunLock::acquire`] retries.
+const LOCK_POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+/// Taskwarrior's default hooks directory, `~/.task/hooks`.
+pub fn default_hooks_dir() -> Option<PathBuf> {
+    dirs::home_dir().map(|home| home.join(".task").join("hooks"))
+}
+
+/// Removes `hook` if it is a symlink to `binary` or a copy of it, as left by `make install`.
+///
+/// Anything else is left alone and reported as [`TaskHookWaybarError::ForeignHook`].
+pub fn uninstall_hook(hook: &Path, binary: &Path) -> Result<(), TaskHookWaybarError> {
+    let metadata = fs::symlink_metadata(hook).map_err(TaskHookWaybarError::with_path(hook))?;
+    let is_ours = if metadata.file_type().is_symlink() {
+        let target = fs::canonicalize(hook).ok();
+        target.is_some() && target == fs::canonicalize(binary).ok()
+    } else {
+        metadata.is_file()
+            && fs::read(hook).map_err(TaskHookWaybarError::with_path(hook))?
+                == fs::read(binary).map_err(TaskHookWaybarError::with_path(binary))?
+    };
+    if !is_ours {
+        return Err(TaskHookWaybarError::ForeignHook(hook.to_path_buf()));
+    }
+    fs::remove_file(hook).map_err(TaskHookWaybarError::with_path(hook))
+}
+
+/// An exclusive `flock(2)` on a lock file, released when dropped.
+///
+/// Serializes the write and signal of hooks firing in quick succession.
+#[derive(Debug)]
+pub struct RunLock {
+    _file: File,
+}
+
+impl RunLock {
+    /// Waits up to `timeout` for the lock at `path`, creating the file if needed.
+    /// Returns `None` if another run still holds it after that.
+    pub fn acquire(path: &Path, timeout: Duration) -> Result<Option<Self>, TaskHookWaybarError> {
+        let file = fs::OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(path)
+            .map_err(TaskHookWaybarError::with_path(path))?;
+        let deadline = Instant::now() + timeout;
+        loop {
+            let result = unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) };
+            if result == 0 {
+                return Ok(Some(RunLock { _file: file }));
+            }
+            let e = std::io::Error::last_os_error();
+            if e.kind() != ErrorKind::WouldBlock {
+                return Err(TaskHookWaybarError::with_path(path)(e));
+            }
+            if Instant::now() >= deadline {
+                return Ok(None);
+            }
+            std::thread::sleep(LOCK_POLL_INTERVAL);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_setup_logging_twice() {
+        let dir = tempfile::tempdir().unwrap();
+        let log_file_path = dir.path().join("waybar-task-hook.log");
+
+        assert!(setup_logging(&log_file_path).is_ok());
+        assert!(setup_logging(&log_file_path).is_ok());
+        assert!(setup_logging(&dir.path().join("other.log")).is_ok());
+    }
+
+    #[test]
+    fn test_run_iterations() {
+        let dir = tempfile::tempdir().unwrap();
+        let log_file_path = dir.path().join("waybar-task-hook.log");
+
+        let mut ran = Vec::new();
+        let result = run_iterations(&log_file_path, 2, Duration::ZERO, |index| {
+            info!("Iteration {}", index);
+            ran.push(index);
+            Ok(())
+        });
+        assert!(result.is_ok());
+        assert_eq!(ran, vec![0, 1]);
+
+        let result = run_iterations(&log_file_path, 2, Duration::ZERO, |_| {
+            Err(TaskHookWaybarError::InvalidArgument("stop".into()))
+        });
+        assert!(result.is_err());
+    }
+
+    fn lookup(name: &str) -> Option<String> {
+        (name == "XDG_CACHE_HOME").then(|| "/home/user/.cache".to_string())
+    }
+
+    fn expand(path: &str) -> PathBuf {
+        expand_path_with(Path::new(path), Some(Path::new("/home/user")), lookup)
+    }
+
+    #[test]
+    fn test_uninstall_hook() {
+        let dir = tempfile::tempdir().unwrap();
+        let binary = dir.path().join("on-exit-hook-waybar");
+        fs::write(&binary, b"\x7fELF hook").unwrap();
+        let hooks_dir = dir.path().join("hooks");
+        fs::create_dir(&hooks_dir).unwrap();
+        let hook = hooks_dir.join("on-exit-hook-waybar");
+
+        fs::copy(&binary, &hook).unwrap();
+        uninstall_hook(&hook, &binary).unwrap();
+        assert!(!hook.exists());
+
+        std::os::unix::fs::symlink(&binary, &hook).unwrap();
+        uninstall_hook(&hook, &binary).unwrap();
+        assert!(fs::symlink_metadata(&hook).is_err());
+        assert!(binary.exists());
+
+        fs::write(&hook, b"#!/bin/sh\necho other hook").unwrap();
+        assert!(matches!(
+            uninstall_hook(&hook, &binary),
+            Err(TaskHookWaybarError::ForeignHook(_))
+        ));
+        let other = dir.path().join("other");
+        fs::write(&other, b"other").unwrap();
+        fs::remove_file(&hook).unwrap();
+        std::os::unix::fs::symlink(&other, &hook).unwrap();
+        assert!(matches!(
+            uninstall_hook(&hook, &binary),
+            Err(TaskHookWaybarError::ForeignHook(_))
+        ));
+        assert!(fs::symlink_metadata(&hook).is_ok());
+    }
+
+    #[test]
+    fn test_prefix_within_bytes() {
+        assert_eq!(prefix_within_bytes("short", 10), "short");
+        assert_eq!(prefix_within_bytes("äöü", 3), "ä");
+        assert_eq!(prefix_within_bytes("äöü", 4), "äö");
+        assert_eq!(prefix_within_bytes("äöü", 1), "");
+    }
+
+    #[test]
+    fn test_truncate_chars() {
+        assert_eq!(truncate_chars("short", 10), "short");
+        assert_eq!(truncate_chars("exactly10!", 10), "exactly10!");
+        assert_eq!(truncate_chars("a bit too long", 10), "a bit too…");
+        assert_eq!(truncate_chars("äöüäöü", 3), "äö…");
+        assert_eq!(truncate_chars("anything", 0), "");
+    }
+
+    #[test]
+    fn test_tail_lines() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("waybar-task-hook.log");
+        fs::write(&path, "first\nsecond\nthird\nfourth\n").unwrap();
+
+        assert_eq!(tail_lines(&path, 2).unwrap(), ["third", "fourth"]);
+        assert_eq!(tail_lines(&path, 10).unwrap().len(), 4);
+        assert!(tail_lines(&dir.path().join("missing.log"), 2).is_err());
+    }
+
+    #[test]
+    fn test_file_age_of_backdated_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("waybar-tasks.json");
+        let file = File::create(&path).unwrap();
+
+        let now = SystemTime::now();
+        file.set_modified(now - Duration::from_secs(3 * 60 * 60))
+            .unwrap();
+
+        let age = file_age(&path, now).unwrap();
+        assert_eq!(age.as_secs(), 3 * 60 * 60);
+
+        assert!(matches!(
+            file_age(&dir.path().join("missing.json"), now),
+            Err(TaskHookWaybarError::FileWithPath { .. })
+        ));
+    }
+
+    #[test]
+    fn test_expand_home() {
+        assert_eq!(
+            expand("~/tasks.json"),
+            PathBuf::from("/home/user/tasks.json")
+        );
+        assert_eq!(expand("~"), PathBuf::from("/home/user"));
+        assert_eq!(expand("/tmp/~file"), PathBuf::from("/tmp/~file"));
+    }
+
+    #[test]
+    fn test_expand_variables() {
+        assert_eq!(
+            expand("$XDG_CACHE_HOME/waybar-tasks.json"),
+            PathBuf::from("/home/user/.cache/waybar-tasks.json")
+        );
+        assert_eq!(
+            expand("${XDG_CACHE_HOME}/waybar-tasks.json"),
+            PathBuf::from("/home/user/.cache/waybar-tasks.json")
+        );
+    }
+
+    #[test]
+    fn test_expand_undefined_variables_unchanged() {
+        assert_eq!(
+            expand("$UNDEFINED/tasks.json"),
+            PathBuf::from("$UNDEFINED/tasks.json")
+        );
+        assert_eq!(expand("${UNDEFINED}/a"), PathBuf::from("${UNDEFINED}/a"));
+        assert_eq!(expand("${unterminated"), PathBuf::from("${unterminated"));
+        assert_eq!(expand("/price$"), PathBuf::from("/price$"));
+    }
+
+    #[test]
+    fn test_resolve_cache_dir_falls_back_to_temp_dir() {
+        assert_eq!(resolve_cache_dir_with(|| None), std::env::temp_dir());
+        assert_eq!(
+            resolve_cache_dir_with(|| Some(PathBuf::from("/home/user/.cache"))),
+            PathBuf::from("/home/user/.cache")
+        );
+    }
+
+    #[test]
+    fn test_timings_summary() {
+        let timings = Timings {
+            steps: vec![
+                ("task export", Duration::from_micros(12_340)),
+                ("write", Duration::from_micros(250)),
+            ],
+        };
+        assert_eq!(
+            timings.summary(),
+            "task export      12.3 ms\n\
+             write             0.2 ms\n\
+             total            12.6 ms\n"
+        );
+
+        let mut timings = Timings::default();
+        assert_eq!(timings.time("signal", || 42), 42);
+        assert!(timings.summary().starts_with("signal "));
+    }
+
+    #[test]
+    fn test_run_lock_contention() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("waybar-tasks.json.lock");
+
+        let lock = RunLock::acquire(&path, Duration::ZERO).unwrap();
+        assert!(lock.is_some());
+
+        let contender = {
+            let path = path.clone();
+            std::thread::spawn(move || RunLock::acquire(&path, Duration::from_millis(50)))
+        };
+        assert!(contender.join().unwrap().unwrap().is_none());
+
+        let waiter = {
+            let path = path.clone();
+            std::thread::spawn(move || RunLock::acquire(&path, Duration::from_secs(5)))
+        };
+        std::thread::sleep(Duration::from_millis(50));
+        drop(lock);
+        assert!(waiter.join().unwrap().unwrap().is_some());
+    }
+
+    #[test]
+    fn test_quiet_writer() {
+        let mut quiet = QuietWriter::new(Vec::new(), true);
+        writeln!(quiet, "Exported to waybar.").unwrap();
+        assert!(quiet.out.is_empty());
+
+        let mut loud = QuietWriter::new(Vec::new(), false);
+        writeln!(loud, "Exported to waybar.").unwrap();
+        assert_eq!(loud.out, b"Exported to waybar.\n");
+    }
+}
@@ -0,0 +1,197 @@
+use crate::errors::TaskHookWaybarError;
+use serde_json::Value;
+use std::path::{Path, PathBuf};
+
+/// The file names waybar looks for in `$XDG_CONFIG_HOME/waybar`, in order.
+const WAYBAR_CONFIG_FILE_NAMES: [&str; 2] = ["config.jsonc", "config"];
+
+/// The waybar config waybar itself would load, if there is one.
+pub fn default_waybar_config_path() -> Option<PathBuf> {
+    let dir = dirs::config_dir()?.join("waybar");
+    WAYBAR_CONFIG_FILE_NAMES
+        .iter()
+        .map(|name| dir.join(name))
+        .find(|path| path.is_file())
+}
+
+/// Reads the `signal` of the custom module showing `output_path` from the waybar config at
+/// `path`, see [`find_module_signal`].
+pub fn read_module_signal(
+    path: &Path,
+    output_path: &Path,
+) -> Result<Option<i32>, TaskHookWaybarError> {
+    let content = std::fs::read_to_string(path).map_err(TaskHookWaybarError::with_path(path))?;
+    let output_file_name = output_path
+        .file_name()
+        .map(|name| name.to_string_lossy())
+        .unwrap_or_default();
+    Ok(find_module_signal(&content, &output_file_name)?)
+}
+
+/// Finds the first `custom/*` module whose `exec` reads a file named `output_file_name`
+/// and returns its `signal`. The config may hold a single bar or an array of bars. An empty
+/// name finds nothing.
+pub fn find_module_signal(
+    content: &str,
+    output_file_name: &str,
+) -> Result<Option<i32>, serde_json::Error> {
+    let config: Value = serde_json::from_str(&strip_jsonc(content))?;
+    if output_file_name.is_empty() {
+        return Ok(None);
+    }
+    let bars = match &config {
+        Value::Array(bars) => bars.iter().collect(),
+        bar => vec![bar],
+    };
+
+    Ok(bars
+        .iter()
+        .filter_map(|bar| bar.as_object())
+        .flat_map(|bar| bar.iter())
+        .filter(|(name, _)| name.starts_with("custom/"))
+        .find(|(_, module)| {
+            module
+                .get("exec")
+                .and_then(Value::as_str)
+                .is_some_and(|exec| exec_reads_file(exec, output_file_name))
+        })
+        .and_then(|(_, module)| module.get("signal")?.as_i64())
+        .and_then(|signal| i32::try_from(signal).ok()))
+}
+
+/// Whether one of the words of the `exec` command line is a path whose file name is
+/// `file_name`, so `tasks.json` is not found in `cat ~/.cache/waybar-tasks.json`.
+fn exec_reads_file(exec: &str, file_name: &str) -> bool {
+    exec.split(|c: char| c.is_whitespace() || "\"'=;|&<>()".contains(c))
+        .filter_map(|word| Path::new(word).file_name())
+        .any(|name| name == file_name)
+}
+
+/// Turns waybar's JSONC into plain json by removing comments and trailing commas.
+fn strip_jsonc(content: &str) -> String {
+    let mut without_comments = String::with_capacity(content.len());
+    let mut chars = content.chars().peekable();
+    let mut in_string = false;
+    while let Some(c) = chars.next() {
+        if in_string {
+            without_comments.push(c);
+            match c {
+                '\\' => without_comments.extend(chars.next()),
+                '"' => in_string = false,
+                _ => {}
+            }
+            continue;
+        }
+        match (c, chars.peek()) {
+            ('"', _) => {
+                in_string = true;
+                without_comments.push(c);
+            }
+            ('/', Some('/')) => {
+                for c in chars.by_ref() {
+                    if c == '\n' {
+                        without_comments.push(c);
+                        break;
+                    }
+                }
+            }
+            ('/', Some('*')) => {
+                chars.next();
+                let mut previous = '\0';
+                for c in chars.by_ref() {
+                    if previous == '*' && c == '/' {
+                        break;
+                    }
+                    previous = c;
+                }
+            }
+            _ => without_comments.push(c),
+        }
+    }
+
+    let mut json = String::with_capacity(without_comments.len());
+    let mut chars = without_comments.chars().peekable();
+    let mut in_string = false;
+    while let Some(c) = chars.next() {
+        if in_string {
+            json.push(c);
+            match c {
+                '\\' => json.extend(chars.next()),
+                '"' => in_string = false,
+                _ => {}
+            }
+            continue;
+        }
+        if c == ',' {
+            let rest = chars.clone().find(|c| !c.is_whitespace());
+            if matches!(rest, Some('}') | Some(']')) {
+                continue;
+            }
+        }
+        if c == '"' {
+            in_string = true;
+        }
+        json.push(c);
+    }
+    json
+}
+
+/**************
+ * Unit tests *
+ **************/
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_WAYBAR_CONFIG: &str = include_str!("../../sample-waybar-config.jsonc");
+
+    #[test]
+    fn test_find_module_signal_in_sample_config() {
+        assert_eq!(
+            find_module_signal(SAMPLE_WAYBAR_CONFIG, "waybar-tasks.json").unwrap(),
+            Some(8)
+        );
+        assert_eq!(
+            find_module_signal(SAMPLE_WAYBAR_CONFIG, "waybar-work-tasks.json").unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn test_find_module_signal_in_multiple_bars() {
+        let content = r#"[
+            /* the top bar */
+            { "custom/clock": { "exec": "date", "signal": 3 } },
+            {
+                "custom/work": {
+                    "exec": "cat ~/.cache/work.json", // "signal": 4
+                    "signal": 9,
+                },
+            },
+        ]"#;
+        assert_eq!(find_module_signal(content, "work.json").unwrap(), Some(9));
+    }
+
+    #[test]
+    fn test_find_module_signal_matches_whole_file_names() {
+        let content = r#"{
+            "custom/other": { "exec": "cat ~/.cache/waybar-tasks.json", "signal": 3 },
+            "custom/tasks": { "exec": "jq -c . \"$HOME/.cache/tasks.json\"", "signal": 9 },
+        }"#;
+        assert_eq!(find_module_signal(content, "tasks.json").unwrap(), Some(9));
+        assert_eq!(
+            find_module_signal(content, "waybar-tasks.json").unwrap(),
+            Some(3)
+        );
+        assert_eq!(find_module_signal(content, "ks.json").unwrap(), None);
+        assert_eq!(find_module_signal(content, "").unwrap(), None);
+    }
+
+    #[test]
+    fn test_strip_jsonc_keeps_strings() {
+        let content = r#"{"exec": "echo // not a comment, }", "format": "a\"/*b*/"}"#;
+        assert_eq!(strip_jsonc(content), content);
+        assert!(find_module_signal("{ invalid", "waybar-tasks.json").is_err());
+    }
+}